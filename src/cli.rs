@@ -14,13 +14,19 @@ pub type Paths = Vec<PathBuf>;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// wad data paths
+    /// wad data paths, wads, pk3/zip archives or directories
     #[arg(value_delimiter = ',', value_name = "WAD")]
     pub wad_paths: Vec<PathBuf>,
     #[arg(short, long,  value_name = "map_name")]
     pub map_name: Option<String>,
     #[arg(short = 'l', long)]
     pub list_maps: bool,
+    /// check the wads for problems and exit
+    #[arg(short = 'c', long)]
+    pub validate: bool,
+    /// print the ENDOOM screen and exit
+    #[arg(short = 'e', long)]
+    pub endoom: bool,
     #[arg(short = 'x', long, default_value = "1024")]
     pub screen_width: i16,
     #[arg(short = 'y', long, default_value = "768")]
@@ -8,8 +8,8 @@ pub fn draw_player<'m, 'c, M: Manager + FlagsData + ColoursStore>(canvas: &'c mu
     let map = &context.current_map;
 
     let scaled_pos = helpers::scale_xy(
-        player.x,
-        player.y,
+        player.x.to_int() as i16,
+        player.y.to_int() as i16,
         &map.map_bounds(),
         (manager.screen_width(), manager.screen_height()),
         30,
@@ -29,7 +29,7 @@ pub fn draw_map_bsp<'m, 'c, M: Manager + FlagsData + ColoursStore>(canvas: &'c m
     let bounds = (manager.screen_width(), manager.screen_height());
     let boarder: i16 = 30;
 
-    for node in map.traverse_bsp((player.x, player.y)) {
+    for node in map.traverse_bsp(player.position()) {
         let ((fx, fy), (fw, fh)) = get_bounding_box(&node.front_bbox, &map,bounds, boarder);
         let ((bx, by), (bw, bh)) = get_bounding_box(&node.back_bbox, &map,bounds, boarder);
 
@@ -120,9 +120,9 @@ pub fn draw_map_lines_bsp<'m, 'c, M: Manager + FlagsData + ColoursStore>(canvas:
     );
 
 
-    let nodes: Vec<wad::Node> = map.traverse_bsp((player.x, player.y)).collect();
+    let nodes: Vec<wad::Node> = map.traverse_bsp(player.position()).collect();
 
-    let segs_by_sub_sector_id = map.segs_from_nodes(&nodes, (player.x, player.y));
+    let segs_by_sub_sector_id = map.segs_from_nodes(&nodes, player.position());
 
 
     let segs_to_draw = &segs_by_sub_sector_id[sub_sector_id];
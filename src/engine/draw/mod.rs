@@ -21,8 +21,8 @@ pub use traits::*;
 
 pub type Flags<'a> = Ref<'a, HashMap<String, bool>>;
 pub type MutFlags<'a> = RefMut<'a, HashMap<String, bool>>;
-pub type Colours<'a> = Ref<'a, HashMap<u16, Color>>;
-pub type MutColours<'a> = RefMut<'a, HashMap<u16, Color>>;
+pub type Colours<'a> = Ref<'a, HashMap<u32, Color>>;
+pub type MutColours<'a> = RefMut<'a, HashMap<u32, Color>>;
 pub type Layers<Draw> = HashMap<String, Layer<Draw>>;
 
 /// A Drawing layer
@@ -60,7 +60,7 @@ pub struct Draw2D {
     layers: Layers<Self>,
     enabled_layers: Vec<String>,
     meta: RefCell<HashMap<String, bool>>,
-    colours: RefCell<HashMap<u16, Color>>,
+    colours: RefCell<HashMap<u32, Color>>,
 }
 
 impl Manager for Draw2D {
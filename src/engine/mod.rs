@@ -97,8 +97,8 @@ impl Engine<Init> {
     }
 }
 impl Engine<MainMenu> {
-    pub fn start(self, wad_name: &str, map_name: &str) -> CliResult<'static, Engine<InGame>> {
-        let map = self.reader.get_map(wad_name, map_name).unwrap();
+    pub fn start(self, map_name: &str) -> CliResult<'static, Engine<InGame>> {
+        let (map, _) = self.reader.find_map(map_name)?;
         let player_thing: wad::Thing = map.things[0].clone().into();
 
         let player = Player::new(player_thing);
@@ -1,13 +1,13 @@
 use super::*;
 
-use wad::{ThingFlags, Thing};
+use wad::{ThingFlags, Thing, Fixed, Angle};
 
 
 #[derive(Debug)]
 pub struct Player {
-    pub x: i16,
-    pub y: i16,
-    pub angle: i16,
+    pub x: Fixed,
+    pub y: Fixed,
+    pub angle: Angle,
     pub doomed_thing_type: i16,
     pub flags: ThingFlags,
 }
@@ -16,17 +16,21 @@ impl Player {
 
     pub fn new(player_thing: Thing) -> Self {
         Self {
-            x: player_thing.x,
-            y: player_thing.y,
-            angle: player_thing.angle_facing,
+            x: player_thing.x.into(),
+            y: player_thing.y.into(),
+            angle: Angle::from_degrees(player_thing.angle_facing as f64),
             doomed_thing_type: player_thing.doomed_thing_type,
             flags: player_thing.flags,
         }
     }
 
-    pub fn update_dir(&mut self, x: i16, y: i16, angle: i16) {
+    pub fn update_dir(&mut self, x: Fixed, y: Fixed, angle: Angle) {
         self.x = x;
         self.y = y;
         self.angle = angle;
     }
+
+    pub fn position(&self) -> (Fixed, Fixed) {
+        (self.x, self.y)
+    }
 }
\ No newline at end of file
@@ -11,19 +11,44 @@ use engine::GameLoop;
 fn main() -> errors::CliResult<'static> {
     let args = cli::args();
 
-    let wad_name = args.wad_paths.first().unwrap().file_stem().unwrap().to_str().unwrap().to_string();
+    // Validating and printing ENDOOM only need the wads, not a window
+    if args.validate {
+        let reader = wad::Reader::new(args)?;
+        let mut error_count = 0;
+        for (wad_name, problems) in reader.validate() {
+            for problem in problems.iter() {
+                println!("{wad_name}: {problem}");
+            }
+            error_count += problems.iter().filter(|p| p.severity == wad::Severity::Error).count();
+        }
+        if error_count > 0 {
+            return Err(wad::Error::Invalid(error_count).into());
+        }
+        return Ok(());
+    }
+
+    if args.endoom {
+        let reader = wad::Reader::new(args)?;
+        print!("{}", reader.end_doom()?.to_ansi());
+        return Ok(());
+    }
+
     let engine = engine::Engine::new(&args)?;
 
     if args.list_maps {
-        for map_name_lump in engine.reader.get_map_list(&wad_name).unwrap().iter() {
-            println!("{}", map_name_lump.name);
+        for map_name in engine.reader.map_list().iter() {
+            println!("{map_name}");
         }
         return Ok(());
     }
 
     let engine = engine.set_up().unwrap();
-    let mut engine = engine.start("DOOM", "E1M1").unwrap();
+    let mut engine = engine.start(args.map_name.as_deref().unwrap_or("E1M1"))?;
     
     engine.main_loop();
+
+    if let Ok(end_doom) = engine.reader.end_doom() {
+        print!("{}", end_doom.to_ansi());
+    }
     Ok(())
 }
\ No newline at end of file
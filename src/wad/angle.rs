@@ -0,0 +1,183 @@
+use super::*;
+
+use std::f64::consts::TAU;
+use std::fmt::{self, Display};
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::sync::OnceLock;
+
+/// A binary angle measurement, the full circle is the whole range of a `u32` so angles
+/// wrap around on their own. East is 0 and angles increase anticlockwise
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Angle(pub u32);
+
+impl Angle {
+    pub const ANG45: Self = Self(0x2000_0000);
+    pub const ANG90: Self = Self(0x4000_0000);
+    pub const ANG180: Self = Self(0x8000_0000);
+    pub const ANG270: Self = Self(0xC000_0000);
+
+    /// Number of entries in the fine sine table for a full circle
+    pub const FINE_ANGLES: usize = 8192;
+    const FINE_SHIFT: u32 = 19;
+
+    pub fn from_degrees(degrees: f64) -> Self {
+        Self::from_turns(degrees / 360.0)
+    }
+
+    pub fn to_degrees(self) -> f64 {
+        self.to_turns() * 360.0
+    }
+
+    pub fn from_radians(radians: f64) -> Self {
+        Self::from_turns(radians / TAU)
+    }
+
+    pub fn to_radians(self) -> f64 {
+        self.to_turns() * TAU
+    }
+
+    fn from_turns(turns: f64) -> Self {
+        Self((turns.rem_euclid(1.0) * 4_294_967_296.0).round() as u64 as u32)
+    }
+
+    fn to_turns(self) -> f64 {
+        self.0 as f64 / 4_294_967_296.0
+    }
+
+    /// Segs store only the upper 16 bits
+    pub fn from_i16(value: i16) -> Self {
+        Self((value as u16 as u32) << 16)
+    }
+
+    pub fn to_i16(self) -> i16 {
+        (self.0 >> 16) as u16 as i16
+    }
+
+    /// Index into the fine tables, the angle's upper 13 bits
+    pub fn fine(self) -> usize {
+        (self.0 >> Self::FINE_SHIFT) as usize
+    }
+
+    pub fn sin(self) -> Fixed {
+        fine_sine()[self.fine()]
+    }
+
+    /// The sine table runs on a quarter circle so it doubles as the cosine table
+    pub fn cos(self) -> Fixed {
+        fine_sine()[self.fine() + Self::FINE_ANGLES / 4]
+    }
+
+    /// Vanilla's tangent table covers -90 to 90 degrees, which repeats every half circle
+    pub fn tan(self) -> Fixed {
+        fine_tangent()[(self + Self::ANG90).fine() % (Self::FINE_ANGLES / 2)]
+    }
+}
+
+/// `finesine` from vanilla, 5/4 of a circle sampled half way between each fine angle.
+/// Vanilla's tables were generated in single precision, which matters most for the
+/// tangents either side of 90 degrees, so every entry is worked out the same way
+pub fn fine_sine() -> &'static [Fixed] {
+    static TABLE: OnceLock<Vec<Fixed>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let step = (TAU / Angle::FINE_ANGLES as f64) as f32;
+        (0..Angle::FINE_ANGLES * 5 / 4)
+            .map(|i| {
+                let angle = (i as f32 + 0.5) * step;
+                Fixed(((angle as f64).sin() as f32 * Fixed::ONE.0 as f32) as i32)
+            })
+            .collect()
+    })
+}
+
+/// `finetangent` from vanilla, half a circle from -90 to 90 degrees
+pub fn fine_tangent() -> &'static [Fixed] {
+    static TABLE: OnceLock<Vec<Fixed>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let step = (TAU / Angle::FINE_ANGLES as f64) as f32;
+        (0..Angle::FINE_ANGLES / 2)
+            .map(|i| {
+                let angle = (i as f32 - (Angle::FINE_ANGLES / 4) as f32 + 0.5) * step;
+                Fixed(((angle as f64).tan() as f32 * Fixed::ONE.0 as f32) as i32)
+            })
+            .collect()
+    })
+}
+
+impl Display for Angle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}°", self.to_degrees())
+    }
+}
+
+impl Add for Angle {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0.wrapping_add(rhs.0))
+    }
+}
+
+impl Sub for Angle {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0.wrapping_sub(rhs.0))
+    }
+}
+
+impl Neg for Angle {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(self.0.wrapping_neg())
+    }
+}
+
+impl AddAssign for Angle {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Angle {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::Range;
+
+    fn entries(table: &[Fixed], range: Range<usize>) -> Vec<i32> {
+        table[range].iter().map(|fixed| fixed.0).collect()
+    }
+
+    #[test]
+    fn fine_tangents_match_vanilla() {
+        assert_eq!(fine_tangent().len(), 4096);
+        assert_eq!(entries(fine_tangent(), 0..9), [
+            -170910304, -56965752, -34178904, -24413316, -18988036, -15535599, -13145455, -11392683, -10052327,
+        ]);
+        assert_eq!(entries(fine_tangent(), 2046..2050), [-75, -25, 25, 75]);
+    }
+
+    #[test]
+    fn fine_sines_match_vanilla() {
+        assert_eq!(fine_sine().len(), 10240);
+        assert_eq!(entries(fine_sine(), 0..12), [25, 75, 125, 175, 226, 276, 326, 376, 427, 477, 527, 578]);
+        assert_eq!(entries(fine_sine(), 2047..2049), [65535, 65535]);
+        assert_eq!(entries(fine_sine(), 4096..4098), [-25, -75]);
+    }
+
+    #[test]
+    fn angles_index_the_fine_tables() {
+        assert_eq!(Angle(0).sin(), Fixed(25));
+        assert_eq!(Angle(0).cos(), Fixed(65535));
+        assert_eq!(Angle::ANG90.sin(), Fixed(65535));
+        assert_eq!(Angle::ANG45.tan(), fine_tangent()[3072]);
+        assert_eq!(Angle(0).tan(), Fixed(25));
+    }
+}
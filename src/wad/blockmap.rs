@@ -0,0 +1,142 @@
+use super::*;
+
+/// The BLOCKMAP lump, a grid of 128x128 cells over the map listing the linedefs that cross
+/// each cell, used to cut down on the linedefs checked for collisions
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Clone)]
+#[brw(little)]
+pub struct BlockMap {
+    pub x_grid_origin: i16,
+    pub y_grid_origin: i16,
+    pub columns: i16,
+    pub rows: i16,
+    /// Offset in 16-bit words from the start of the lump to each cell's block list
+    #[br(parse_with = binrw::helpers::count(columns.max(0) as usize * rows.max(0) as usize))]
+    pub offsets: Vec<u16>,
+    /// Everything after the offsets as stored, block lists can be shared between cells so
+    /// this is what gets written back out
+    #[br(parse_with = binrw::helpers::until_eof)]
+    pub list_data: Vec<u16>,
+    /// The linedef ids in each cell, row by row
+    #[br(ignore)]
+    #[bw(ignore)]
+    pub block_lists: Vec<Vec<u16>>,
+}
+
+impl BlockMap {
+    pub const CELL_SIZE: i32 = 128;
+    const LIST_END: u16 = 0xFFFF;
+
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let mut block_map = Self::read(&mut Cursor::new(data))
+            .map_err(|err| Self::error(err.root_cause().to_string()))?;
+
+        let list_start = 4 + block_map.offsets.len();
+        block_map.block_lists = block_map.offsets.iter().enumerate().map(|(cell, offset)| {
+            let start = (*offset as usize).checked_sub(list_start)
+                .filter(|start| *start < block_map.list_data.len())
+                .ok_or_else(|| Self::error(format!("cell {cell} has an offset of {offset} words outside of the block lists")))?;
+            let list = &block_map.list_data[start..];
+            let end = list.iter().position(|id| *id == Self::LIST_END)
+                .ok_or_else(|| Self::error(format!("block list for cell {cell} has no end marker")))?;
+            // Lists start with a 0 that vanilla mistakenly treats as linedef 0
+            let skip = if list.first() == Some(&0) { 1 } else { 0 };
+            Ok(list[skip.min(end)..end].to_vec())
+        }).collect::<Result<_, Error>>()?;
+
+        Ok(block_map)
+    }
+
+    fn error(reason: String) -> Error {
+        Error::Decoding("BLOCKMAP".to_string(), reason)
+    }
+
+    /// The column and row of the cell covering a point
+    pub fn cell_at(&self, (x, y): (i32, i32)) -> Option<(usize, usize)> {
+        let column = (x - self.x_grid_origin as i32).div_euclid(Self::CELL_SIZE);
+        let row = (y - self.y_grid_origin as i32).div_euclid(Self::CELL_SIZE);
+        if column < 0 || row < 0 || column >= self.columns as i32 || row >= self.rows as i32 {
+            None
+        } else {
+            Some((column as usize, row as usize))
+        }
+    }
+
+    pub fn block_list(&self, column: usize, row: usize) -> Option<&Vec<u16>> {
+        if column >= self.columns.max(0) as usize { return None }
+        self.block_lists.get(row * self.columns as usize + column)
+    }
+
+    /// Linedef ids in the cell covering a point, empty outside of the grid
+    pub fn line_defs_at(&self, point: (i32, i32)) -> &[u16] {
+        self.cell_at(point)
+            .and_then(|(column, row)| self.block_list(column, row))
+            .map(|list| list.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Linedef ids in every cell overlapping a bounding box, sorted without duplicates
+    pub fn line_defs_in_box(&self, (x1, y1): (i32, i32), (x2, y2): (i32, i32)) -> Vec<u16> {
+        let clamp_cell = |value: i32, origin: i16, count: i16| {
+            ((value - origin as i32).div_euclid(Self::CELL_SIZE)).clamp(0, count as i32 - 1)
+        };
+        if self.columns <= 0 || self.rows <= 0 { return Vec::new() }
+        let (min_x, max_x) = (x1.min(x2), x1.max(x2));
+        let (min_y, max_y) = (y1.min(y2), y1.max(y2));
+        if max_x < self.x_grid_origin as i32 || max_y < self.y_grid_origin as i32 { return Vec::new() }
+        if min_x >= self.x_grid_origin as i32 + self.columns as i32 * Self::CELL_SIZE
+            || min_y >= self.y_grid_origin as i32 + self.rows as i32 * Self::CELL_SIZE { return Vec::new() }
+
+        let mut line_defs: Vec<u16> = (clamp_cell(min_y, self.y_grid_origin, self.rows)..=clamp_cell(max_y, self.y_grid_origin, self.rows))
+            .flat_map(|row| (clamp_cell(min_x, self.x_grid_origin, self.columns)..=clamp_cell(max_x, self.x_grid_origin, self.columns))
+                .map(move |column| (column as usize, row as usize)))
+            .filter_map(|(column, row)| self.block_list(column, row))
+            .flatten()
+            .copied()
+            .collect();
+        line_defs.sort_unstable();
+        line_defs.dedup();
+        line_defs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_maps::*;
+
+    fn block_map() -> BlockMap {
+        BlockMap::new(&square_room().into_iter().find(|(name, _)| *name == "BLOCKMAP").unwrap().1).unwrap()
+    }
+
+    #[test]
+    fn block_lists_skip_the_leading_zero() {
+        let block_map = block_map();
+        assert_eq!(block_map.block_lists, [vec![0, 3], vec![3, 2], vec![0, 1], vec![0, 1]]);
+        assert_eq!(block_map.block_list(1, 0), Some(&vec![3, 2]));
+        assert_eq!(block_map.block_list(2, 0), None);
+    }
+
+    #[test]
+    fn boxes_collect_the_linedefs_of_every_cell_they_touch() {
+        let block_map = block_map();
+        assert_eq!(block_map.line_defs_in_box((0, 0), (10, 10)), [0, 3]);
+        assert_eq!(block_map.line_defs_in_box((100, 0), (130, 10)), [0, 2, 3]);
+        assert_eq!(block_map.line_defs_in_box((-100, -100), (1000, 1000)), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn boxes_off_the_grid_touch_nothing() {
+        let block_map = block_map();
+        assert_eq!(block_map.line_defs_in_box((1000, 0), (1100, 10)), Vec::<u16>::new());
+        assert_eq!(block_map.line_defs_in_box((0, 1000), (10, 1100)), Vec::<u16>::new());
+        assert_eq!(block_map.line_defs_in_box((-100, 0), (-50, 10)), Vec::<u16>::new());
+        assert_eq!(block_map.line_defs_in_box((0, -100), (10, -50)), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn bad_block_maps_are_dropped_when_loading_a_map() {
+        let map = map(&with_lump(square_room(), "BLOCKMAP", words(&[-8, -8, 1, 1, 40, 0, -1]))).unwrap();
+        assert_eq!(map.block_map, None);
+        assert!(BlockMap::new(&words(&[-8, -8, 1, 1, 5, 0])).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use super::*;
+
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Clone, Copy)]
+#[brw(little)]
+pub struct DemoHeader {
+    pub version: u8,
+    pub skill: u8,
+    pub episode: u8,
+    pub map: u8,
+    pub deathmatch: u8,
+    #[br(map = |x: u8| x != 0)]
+    #[bw(map = |x: &bool| *x as u8)]
+    pub respawn: bool,
+    #[br(map = |x: u8| x != 0)]
+    #[bw(map = |x: &bool| *x as u8)]
+    pub fast: bool,
+    #[br(map = |x: u8| x != 0)]
+    #[bw(map = |x: &bool| *x as u8)]
+    pub no_monsters: bool,
+    pub console_player: u8,
+    #[br(map = |x: [u8; 4]| x.map(|p| p != 0))]
+    #[bw(map = |x: &[bool; 4]| x.map(|p| p as u8))]
+    pub players_in_game: [bool; 4],
+}
+
+/// A single player's input for a single tic
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct TicCmd {
+    pub forward_move: i8,
+    pub side_move: i8,
+    /// Stored in demos as only the high byte unless the demo uses long tics
+    pub angle_turn: i16,
+    pub buttons: u8,
+}
+
+impl TicCmd {
+    pub const ATTACK: u8 = 0x01;
+    pub const USE: u8 = 0x02;
+    pub const CHANGE_WEAPON: u8 = 0x04;
+    pub const WEAPON_MASK: u8 = 0x38;
+    pub const WEAPON_SHIFT: u8 = 3;
+    pub const SPECIAL: u8 = 0x80;
+
+    pub fn attacking(&self) -> bool {
+        self.buttons & Self::SPECIAL == 0 && self.buttons & Self::ATTACK != 0
+    }
+
+    pub fn using(&self) -> bool {
+        self.buttons & Self::SPECIAL == 0 && self.buttons & Self::USE != 0
+    }
+
+    /// The weapon slot being switched to, if any
+    pub fn weapon_change(&self) -> Option<u8> {
+        (self.buttons & Self::SPECIAL == 0 && self.buttons & Self::CHANGE_WEAPON != 0)
+            .then_some((self.buttons & Self::WEAPON_MASK) >> Self::WEAPON_SHIFT)
+    }
+}
+
+/// A recorded demo (DEMO1-3 lumps or .lmp files) in the v1.4 - v1.9 format,
+/// each tic holds one TicCmd per player in game
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Demo {
+    pub header: DemoHeader,
+    pub tics: Vec<Vec<TicCmd>>,
+}
+
+impl Demo {
+    pub const VERSION_1_9: u8 = 109;
+    /// Ports recording with full resolution turning
+    pub const VERSION_LONG_TICS: u8 = 111;
+    pub const END_MARKER: u8 = 0x80;
+
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let header = DemoHeader::read(&mut Cursor::new(data))
+            .map_err(|err| Self::error(err.root_cause().to_string()))?;
+        if header.version < 104 || header.version > Self::VERSION_LONG_TICS {
+            return Err(Self::error(format!("version {} is not supported", header.version)));
+        }
+
+        let players = header.players_in_game.iter().filter(|p| **p).count();
+        if players == 0 {
+            return Err(Self::error("no players are in game".to_string()));
+        }
+        let cmd_size = if header.version == Self::VERSION_LONG_TICS { 5 } else { 4 };
+        let mut position = 13;
+        let mut tics = Vec::new();
+        loop {
+            match data.get(position) {
+                None => return Err(Self::error(format!("demo ends at {position} without an end marker"))),
+                Some(&Self::END_MARKER) => break,
+                Some(_) => {},
+            }
+            let tic = data.get(position..position + cmd_size * players)
+                .ok_or_else(|| Self::error(format!("tic {} at {position} is cut short", tics.len())))?;
+            tics.push(tic.chunks_exact(cmd_size).map(|cmd| TicCmd {
+                forward_move: cmd[0] as i8,
+                side_move: cmd[1] as i8,
+                angle_turn: if cmd_size == 5 {
+                    i16::from_le_bytes([cmd[2], cmd[3]])
+                } else {
+                    ((cmd[2] as u16) << 8) as i16
+                },
+                buttons: cmd[cmd_size - 1],
+            }).collect());
+            position += cmd_size * players;
+        }
+
+        Ok(Self { header, tics })
+    }
+
+    pub fn from_file(path: &std::path::Path) -> Result<Self, Error> {
+        Self::new(&fs::read(path).map_err(|err| Error::FileOpen(err.to_string()))?)
+    }
+
+    fn error(reason: String) -> Error {
+        Error::Decoding("demo".to_string(), reason)
+    }
+
+    pub fn uses_long_tics(&self) -> bool {
+        self.header.version == Self::VERSION_LONG_TICS
+    }
+
+    /// Tics per second is fixed at 35
+    pub fn duration(&self) -> f32 {
+        self.tics.len() as f32 / 35.0
+    }
+
+    pub fn to_bytes(&self) -> Result<RawData, Error> {
+        let players = self.header.players_in_game.iter().filter(|p| **p).count();
+        let mut cursor = Cursor::new(RawData::new());
+        self.header.write(&mut cursor)
+            .map_err(|err| Error::Writing(err.root_cause().to_string()))?;
+        let mut out = cursor.into_inner();
+
+        for (i, tic) in self.tics.iter().enumerate() {
+            if tic.len() != players {
+                return Err(Error::Writing(format!("tic {i} has {} commands for {players} players", tic.len())));
+            }
+            for cmd in tic {
+                out.extend([cmd.forward_move as u8, cmd.side_move as u8]);
+                if self.uses_long_tics() {
+                    out.extend(cmd.angle_turn.to_le_bytes());
+                } else {
+                    // Round to the nearest high byte like vanilla does when recording
+                    out.push(((cmd.angle_turn as i32 + 128) >> 8) as u8);
+                }
+                out.push(cmd.buttons);
+            }
+        }
+        out.push(Self::END_MARKER);
+        Ok(out)
+    }
+
+    pub fn write(&self, path: &std::path::Path) -> Result<(), Error> {
+        fs::write(path, self.to_bytes()?).map_err(|err| Error::Writing(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demo_data(players_in_game: [u8; 4], tics: &[u8]) -> RawData {
+        [&[Demo::VERSION_1_9, 2, 1, 1, 0, 0, 0, 0, 0], &players_in_game[..], tics].concat()
+    }
+
+    #[test]
+    fn tics_are_read_for_each_player_in_game() {
+        let demo = Demo::new(&demo_data([1, 0, 1, 0], &[25, 0, 1, 1, 0xCE, 0, 0xFF, 0, Demo::END_MARKER])).unwrap();
+        assert_eq!(demo.tics, [[
+            TicCmd { forward_move: 25, side_move: 0, angle_turn: 256, buttons: 1 },
+            TicCmd { forward_move: -50, side_move: 0, angle_turn: -256, buttons: 0 },
+        ]]);
+        assert_eq!(demo.to_bytes().unwrap(), demo_data([1, 0, 1, 0], &[25, 0, 1, 1, 0xCE, 0, 0xFF, 0, Demo::END_MARKER]));
+    }
+
+    #[test]
+    fn demos_without_players_are_rejected() {
+        assert!(matches!(Demo::new(&demo_data([0; 4], &[0, 0, 0, 0, Demo::END_MARKER])), Err(Error::Decoding(..))));
+    }
+
+    #[test]
+    fn truncated_demos_are_rejected() {
+        assert!(matches!(Demo::new(&demo_data([1, 0, 0, 0], &[25, 0, 1, 1, 25, 0])), Err(Error::Decoding(..))));
+        assert!(matches!(Demo::new(&demo_data([1, 0, 0, 0], &[25, 0, 1, 1])), Err(Error::Decoding(..))));
+        assert!(matches!(Demo::new(&[Demo::VERSION_1_9, 2, 1]), Err(Error::Decoding(..))));
+    }
+}
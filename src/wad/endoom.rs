@@ -0,0 +1,178 @@
+use super::*;
+
+/// Code page 437 as unicode, 0 is drawn as a space
+const CP437: [char; 256] = [
+    ' ', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', ' ',
+];
+
+/// VGA colour order (blue is bit 0, red bit 2) to ANSI colour order (red is bit 0, blue bit 2)
+const VGA_TO_ANSI: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextCell {
+    pub character: u8,
+    pub attribute: u8,
+}
+
+impl TextCell {
+    pub fn glyph(&self) -> char {
+        CP437[self.character as usize]
+    }
+
+    /// One of the 16 VGA colours
+    pub fn foreground(&self) -> u8 {
+        self.attribute & 0x0F
+    }
+
+    /// One of the 8 low intensity VGA colours
+    pub fn background(&self) -> u8 {
+        (self.attribute >> 4) & 0x07
+    }
+
+    pub fn blinks(&self) -> bool {
+        self.attribute & 0x80 != 0
+    }
+
+    fn ansi_attributes(&self) -> String {
+        let foreground = self.foreground();
+        let foreground = if foreground > 7 {
+            90 + VGA_TO_ANSI[(foreground - 8) as usize]
+        } else {
+            30 + VGA_TO_ANSI[foreground as usize]
+        };
+        let background = 40 + VGA_TO_ANSI[self.background() as usize];
+        let blink = if self.blinks() { ";5" } else { "" };
+        format!("\x1b[0;{foreground};{background}{blink}m")
+    }
+}
+
+/// The ENDOOM lump, an 80x25 text mode screen shown on quitting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndDoom {
+    pub cells: Vec<TextCell>,
+}
+
+impl EndDoom {
+    pub const WIDTH: usize = 80;
+    pub const HEIGHT: usize = 25;
+
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let size = Self::WIDTH * Self::HEIGHT * 2;
+        let screen = data.get(..size).ok_or_else(|| Error::Decoding(
+            "ENDOOM".to_string(),
+            format!("{} bytes is too short for a {}x{} screen", data.len(), Self::WIDTH, Self::HEIGHT)
+        ))?;
+        Ok(Self {
+            cells: screen.chunks_exact(2)
+                .map(|cell| TextCell { character: cell[0], attribute: cell[1] })
+                .collect(),
+        })
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Option<&TextCell> {
+        if x >= Self::WIDTH { return None }
+        self.cells.get(y * Self::WIDTH + x)
+    }
+
+    /// The screen as plain text, one line per row
+    pub fn to_text(&self) -> String {
+        self.cells.chunks(Self::WIDTH)
+            .map(|row| row.iter().map(TextCell::glyph).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The screen with ANSI escapes for its colours and blinking, attributes are only
+    /// emitted when they change and are reset at the end of every row
+    pub fn to_ansi(&self) -> String {
+        let mut output = String::new();
+        for row in self.cells.chunks(Self::WIDTH) {
+            let mut attribute = None;
+            for cell in row {
+                if attribute != Some(cell.attribute) {
+                    output.push_str(&cell.ansi_attributes());
+                    attribute = Some(cell.attribute);
+                }
+                output.push(cell.glyph());
+            }
+            output.push_str("\x1b[0m\n");
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A screen starting with `cells` and filled with grey on black spaces after them
+    fn screen(cells: &[(u8, u8)]) -> RawData {
+        let mut data: RawData = cells.iter().flat_map(|&(character, attribute)| [character, attribute]).collect();
+        data.resize(EndDoom::WIDTH * EndDoom::HEIGHT * 2, 0);
+        for cell in data.chunks_exact_mut(2).skip(cells.len()) {
+            cell.copy_from_slice(&[b' ', 0x07]);
+        }
+        data
+    }
+
+    #[test]
+    fn cells_are_read_row_by_row() {
+        let mut cells = vec![(b'A', 0x07); EndDoom::WIDTH];
+        cells.push((0xDB, 0x9C));
+        let end_doom = EndDoom::new(&screen(&cells)).unwrap();
+        assert_eq!(end_doom.cells.len(), EndDoom::WIDTH * EndDoom::HEIGHT);
+
+        let cell = end_doom.cell(0, 1).unwrap();
+        assert_eq!((cell.glyph(), cell.foreground(), cell.background(), cell.blinks()), ('█', 12, 1, true));
+        assert_eq!(end_doom.cell(EndDoom::WIDTH, 0), None);
+        assert_eq!(end_doom.cell(0, EndDoom::HEIGHT), None);
+
+        let text = end_doom.to_text();
+        assert_eq!(text.lines().count(), EndDoom::HEIGHT);
+        assert_eq!(text.lines().next().unwrap(), "A".repeat(EndDoom::WIDTH));
+        assert!(text.lines().nth(1).unwrap().starts_with("█ "));
+
+        assert!(matches!(EndDoom::new(&[0; 100]), Err(Error::Decoding(..))));
+    }
+
+    #[test]
+    fn glyphs_are_code_page_437() {
+        let end_doom = EndDoom::new(&screen(&[(0, 0x07), (0x01, 0x07), (b'~', 0x07), (0x81, 0x07), (0xB0, 0x07), (0xFE, 0x07)])).unwrap();
+        assert!(end_doom.to_text().starts_with(" ☺~ü░■ "));
+    }
+
+    #[test]
+    fn ansi_colours_are_swapped_from_vga_and_only_written_on_change() {
+        let end_doom = EndDoom::new(&screen(&[(b'H', 0x1E), (b'i', 0x1E), (b'!', 0x8C), (b'?', 0x74)])).unwrap();
+        let ansi = end_doom.to_ansi();
+        let mut rows = ansi.lines();
+
+        // Yellow on blue, blinking bright red on black, red on grey then the grey on black fill
+        let first = format!(
+            "\x1b[0;93;44mHi\x1b[0;91;40;5m!\x1b[0;31;47m?\x1b[0;37;40m{}\x1b[0m",
+            " ".repeat(EndDoom::WIDTH - 4),
+        );
+        assert_eq!(rows.next().unwrap(), first);
+
+        // Every row starts with its attributes again
+        let rest = format!("\x1b[0;37;40m{}\x1b[0m", " ".repeat(EndDoom::WIDTH));
+        assert!(rows.all(|row| row == rest));
+        assert_eq!(ansi.lines().count(), EndDoom::HEIGHT);
+        assert!(ansi.ends_with("\x1b[0m\n"));
+    }
+}
@@ -11,6 +11,10 @@ pub enum Error {
     FileRead(String),
     Unpacking(UnpackError),
     Reader(String),
+    MissingLump(String, String),
+    Writing(String),
+    Decoding(String, String),
+    Invalid(usize),
     Lump(lumps::Error)
 }
 
@@ -22,6 +26,10 @@ impl  Display for Error  {
             Self::FileRead(message) => write!(f, "Could not Open wad:`{message}`"),
             Self::Unpacking(unpack_error) => write!(f, "{unpack_error}"),
             Self::Reader(message) => write!(f, "Wad Reader Error: `{message}`"),
+            Self::MissingLump(map, lump) => write!(f, "Map '{map}' has no '{lump}' lump"),
+            Self::Writing(message) => write!(f, "Could not Write wad:`{message}`"),
+            Self::Decoding(what, reason) => write!(f, "Could not decode {what}: `{reason}`"),
+            Self::Invalid(error_count) => write!(f, "Validation found {error_count} error(s)"),
             Self::Lump(lumps_error) => write!(f, "Lump processing error: `{lumps_error}`"),
         }
     }
@@ -42,12 +50,14 @@ impl From<UnpackError> for Error {
 #[derive(Debug)]
 pub enum UnpackError {
     Headers(String),
+    Directory(String),
 }
 
 impl  Display for UnpackError  {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Headers(reason) => write!(f, "Failed to unpack Headers: '{}'", reason.to_string()),
+            Self::Directory(reason) => write!(f, "Failed to unpack Directory: '{reason}'"),
         }
     }
 }
@@ -0,0 +1,323 @@
+use super::*;
+
+use std::io::Read;
+
+use binrw::BinResult;
+use flate2::read::ZlibDecoder;
+
+/// ZDBSP's extended node formats, stored in NODES (XNOD/ZNOD) or for GL nodes in
+/// SSECTORS (XGLN, XGL2, XGL3), the Z variants zlib compress everything after the magic
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExtendedFormat {
+    Normal,
+    Gl,
+    /// Widens GL seg linedef ids to 32 bits
+    Gl2,
+    /// Also stores node partitions as 16.16 fixed point
+    Gl3,
+}
+
+impl ExtendedFormat {
+    /// The format and whether it's compressed, from the start of a NODES lump
+    pub fn from_nodes(data: &[u8]) -> Option<(Self, bool)> {
+        match data.get(0..4)? {
+            b"XNOD" => Some((Self::Normal, false)),
+            b"ZNOD" => Some((Self::Normal, true)),
+            _ => None,
+        }
+    }
+
+    /// The format and whether it's compressed, from the start of a SSECTORS lump
+    pub fn from_sub_sectors(data: &[u8]) -> Option<(Self, bool)> {
+        match data.get(0..4)? {
+            b"XGLN" => Some((Self::Gl, false)),
+            b"ZGLN" => Some((Self::Gl, true)),
+            b"XGL2" => Some((Self::Gl2, false)),
+            b"ZGL2" => Some((Self::Gl2, true)),
+            b"XGL3" => Some((Self::Gl3, false)),
+            b"ZGL3" => Some((Self::Gl3, true)),
+            _ => None,
+        }
+    }
+}
+
+/// Extended nodes decoded into the same records as the map's own nodes, with indexes wide
+/// enough for maps too big for the vanilla format
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ExtendedNodes {
+    pub format: ExtendedFormat,
+    /// The map's vertexes the nodes use followed by those the node builder added, at the
+    /// 16.16 fixed point they're stored as
+    pub vertexes: Vec<(Fixed, Fixed)>,
+    pub segments: Vec<Segment>,
+    pub sub_sectors: Vec<SubSector>,
+    pub nodes: Vec<Node>,
+}
+
+impl ExtendedNodes {
+    /// Decode a NODES or SSECTORS lump starting with one of the extended magics, segs don't
+    /// store their angle or offset along their linedef so they're worked out from the
+    /// map's vertexes and linedefs
+    pub fn new(data: &[u8], map_vertexes: &[Vertex], line_defs: &[LineDef]) -> Result<Self, Error> {
+        let (format, compressed) = ExtendedFormat::from_nodes(data)
+            .or_else(|| ExtendedFormat::from_sub_sectors(data))
+            .ok_or_else(|| Self::error("no extended node magic".to_string()))?;
+
+        let result = if compressed {
+            Self::decode(format, &mut ZlibDecoder::new(&data[4..]), map_vertexes, line_defs)
+        } else {
+            Self::decode(format, &mut &data[4..], map_vertexes, line_defs)
+        };
+        result.map_err(|err| Self::error(err.root_cause().to_string()))
+    }
+
+    /// The map's vertexes rounded to whole units, followed by the node builder's
+    pub fn map_vertexes(&self) -> Vec<Vertex> {
+        let round = |coordinate: Fixed| coordinate.round().clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        self.vertexes.iter().enumerate()
+            .map(|(id, (x, y))| Vertex { x: round(*x), y: round(*y), id: id as u32 })
+            .collect()
+    }
+
+    /// Each part of the nodes is a count followed by that many records, only as much as
+    /// the count says is there is read, or inflated for the compressed formats
+    fn section(reader: &mut impl Read, record_size: usize) -> BinResult<(usize, Cursor<RawData>)> {
+        let count = Self::count(reader)?;
+        let size = count * record_size;
+        let mut records = RawData::new();
+        reader.take(size as u64).read_to_end(&mut records)?;
+        if records.len() < size {
+            return Err(binrw::Error::AssertFail {
+                pos: 0,
+                message: format!("{count} records need {size} bytes but there are only {}", records.len()),
+            });
+        }
+        Ok((count, Cursor::new(records)))
+    }
+
+    fn count(reader: &mut impl Read) -> BinResult<usize> {
+        let mut count = [0; 4];
+        reader.read_exact(&mut count)?;
+        Ok(u32::from_le_bytes(count) as usize)
+    }
+
+    fn decode(format: ExtendedFormat, reader: &mut impl Read, map_vertexes: &[Vertex], line_defs: &[LineDef]) -> BinResult<Self> {
+        let original_vertex_count = Self::count(reader)?;
+        if original_vertex_count > map_vertexes.len() {
+            return Err(binrw::Error::AssertFail {
+                pos: 0,
+                message: format!("nodes use {original_vertex_count} of the map's vertexes but it only has {}", map_vertexes.len()),
+            });
+        }
+        let mut vertexes: Vec<(Fixed, Fixed)> = map_vertexes[..original_vertex_count].iter()
+            .map(|vertex| (Fixed::from(vertex.x), Fixed::from(vertex.y)))
+            .collect();
+        let (count, mut cursor) = Self::section(reader, 8)?;
+        for _ in 0..count {
+            let (x, y): (i32, i32) = cursor.read_le()?;
+            vertexes.push((Fixed(x), Fixed(y)));
+        }
+
+        let mut first_segments_id = 0;
+        let mut sub_sectors = Vec::new();
+        let (count, mut cursor) = Self::section(reader, 4)?;
+        for id in 0..count {
+            let segments_count = cursor.read_le::<u32>()?;
+            sub_sectors.push(SubSector { segments_count, first_segments_id, id: id as u32 });
+            first_segments_id += segments_count;
+        }
+
+        // GL segs only store their first vertex, the second is the first of the next seg in
+        // the subsector. Minisegs along partition lines are kept as they're part of the
+        // subsector's outline but they have no linedef to take an angle or offset from
+        let mut raw_segments = Vec::new();
+        let segment_size = if matches!(format, ExtendedFormat::Gl2 | ExtendedFormat::Gl3) { 13 } else { 11 };
+        let (count, mut cursor) = Self::section(reader, segment_size)?;
+        for _ in 0..count {
+            raw_segments.push(match format {
+                ExtendedFormat::Normal => {
+                    let (start, end, line_def, side): (u32, u32, u16, u8) = cursor.read_le()?;
+                    (start, end, (line_def != u16::MAX).then_some(line_def as u32), side)
+                }
+                ExtendedFormat::Gl => {
+                    let (start, _partner, line_def, side): (u32, u32, u16, u8) = cursor.read_le()?;
+                    (start, 0, (line_def != u16::MAX).then_some(line_def as u32), side)
+                }
+                ExtendedFormat::Gl2 | ExtendedFormat::Gl3 => {
+                    let (start, _partner, line_def, side): (u32, u32, u32, u8) = cursor.read_le()?;
+                    (start, 0, (line_def != u32::MAX).then_some(line_def), side)
+                }
+            });
+        }
+        if format != ExtendedFormat::Normal {
+            for sub_sector in sub_sectors.iter() {
+                let range = sub_sector.to_range();
+                for i in range.clone() {
+                    let next = if i + 1 == range.end { range.start } else { i + 1 };
+                    if let Some(start) = raw_segments.get(next).map(|segment| segment.0) {
+                        raw_segments[i].1 = start;
+                    }
+                }
+            }
+        }
+        let segments = raw_segments.iter().enumerate()
+            .map(|(id, (start, end, line_def, side))| Self::segment(id, (*start, *end), *line_def, *side, &vertexes, line_defs))
+            .collect();
+
+        let mut nodes = Vec::new();
+        let (count, mut cursor) = Self::section(reader, if format == ExtendedFormat::Gl3 { 40 } else { 32 })?;
+        for id in 0..count {
+            let partition: [i16; 4] = if format == ExtendedFormat::Gl3 {
+                cursor.read_le::<[i32; 4]>()?.map(|x| (x >> 16) as i16)
+            } else {
+                cursor.read_le()?
+            };
+            nodes.push(Node {
+                x_partion: partition[0],
+                y_partion: partition[1],
+                dx_partion: partition[2],
+                dy_partion: partition[3],
+                front_bbox: cursor.read_le()?,
+                back_bbox: cursor.read_le()?,
+                front_child_id: cursor.read_le()?,
+                back_child_id: cursor.read_le()?,
+                id: id as u32,
+            });
+        }
+
+        Ok(Self { format, vertexes, segments, sub_sectors, nodes })
+    }
+
+    fn segment(id: usize, (start, end): (u32, u32), line_def_id: Option<u32>, side: u8, vertexes: &[(Fixed, Fixed)], line_defs: &[LineDef]) -> Segment {
+        let position = |vertex_id: u32| vertexes.get(vertex_id as usize).map(|(x, y)| (x.to_f64(), y.to_f64())).unwrap_or_default();
+        let (x1, y1) = position(start);
+        let direction = if side == 0 { SegDirection::SameAsLineDef } else { SegDirection::OppositOfLineDef };
+
+        // Measured from the end of the linedef the seg runs away from
+        let offset = line_def_id.and_then(|id| line_defs.get(id as usize)).map_or(0.0, |line_def| {
+            let from = if side == 0 { line_def.start_vertex_id } else { line_def.end_vertex_id };
+            let (x, y) = position(from as u32);
+            (x1 - x).hypot(y1 - y)
+        });
+        Segment {
+            start_vertext_id: start,
+            end_verext_id: end,
+            angle: Segment::angle_between((x1, y1), position(end)),
+            line_def_id,
+            direction,
+            offset: offset as i16,
+            id: id as u32,
+        }
+    }
+
+    fn error(reason: String) -> Error {
+        Error::Decoding("extended nodes".to_string(), reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_maps::*;
+
+    const VERTEXES: [Vertex; 3] = [
+        Vertex { x: 0, y: 0, id: 0 },
+        Vertex { x: 0, y: 128, id: 1 },
+        Vertex { x: 128, y: 0, id: 2 },
+    ];
+
+    /// An XGL2 lump with a single triangular subsector, one of its segs a miniseg
+    fn xgl2(original_vertex_count: u32) -> RawData {
+        let mut data = b"XGL2".to_vec();
+        for value in [original_vertex_count, 0, 1, 3, 3] {
+            data.extend(value.to_le_bytes());
+        }
+        for (start, line_def, side) in [(0u32, 70000u32, 0u8), (1, u32::MAX, 0), (2, 1, 1)] {
+            data.extend(start.to_le_bytes());
+            data.extend(0u32.to_le_bytes());
+            data.extend(line_def.to_le_bytes());
+            data.push(side);
+        }
+        data.extend(0u32.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn gl2_segs_keep_their_32_bit_linedefs() {
+        let nodes = ExtendedNodes::new(&xgl2(3), &VERTEXES, &[]).unwrap();
+        assert_eq!(nodes.format, ExtendedFormat::Gl2);
+        assert_eq!(nodes.segments.iter().map(|segment| segment.line_def_id).collect::<Vec<_>>(), [Some(70000), None, Some(1)]);
+        assert_eq!(nodes.segments.iter().map(|segment| segment.end_verext_id).collect::<Vec<_>>(), [1, 2, 0]);
+        assert_eq!(nodes.sub_sectors[0].to_range(), 0..3);
+    }
+
+    #[test]
+    fn nodes_cant_use_more_vertexes_than_the_map_has() {
+        assert!(matches!(ExtendedNodes::new(&xgl2(4), &VERTEXES, &[]), Err(Error::Decoding(..))));
+    }
+
+    /// An XNOD body with `count` new vertexes along the x axis a quarter unit apart, and a
+    /// single subsector whose seg runs from the map's first vertex to the last new one
+    fn xnod_body(count: u32) -> RawData {
+        let mut body = Vec::new();
+        body.extend(1u32.to_le_bytes());
+        body.extend(count.to_le_bytes());
+        for i in 0..count as i32 {
+            body.extend(((i + 1) << 14).to_le_bytes());
+            body.extend(0i32.to_le_bytes());
+        }
+        for value in [1u32, 1, 1, 0, count] {
+            body.extend(value.to_le_bytes());
+        }
+        body.extend([0xFF, 0xFF, 0]);
+        body.extend(0u32.to_le_bytes());
+        body
+    }
+
+    #[test]
+    fn new_vertexes_keep_their_fraction_and_wide_ids() {
+        let nodes = ExtendedNodes::new(&[b"XNOD".to_vec(), xnod_body(70000)].concat(), &VERTEXES, &[]).unwrap();
+        assert_eq!(nodes.vertexes.len(), 70001);
+        assert_eq!(nodes.vertexes[1], (Fixed(1 << 14), Fixed(0)));
+        assert_eq!(nodes.vertexes[70000], (Fixed::from_int(17500), Fixed(0)));
+        assert_eq!(nodes.segments[0].end_verext_id, 70000);
+
+        // Rounded to the nearest unit for the map's records
+        let vertexes = nodes.map_vertexes();
+        assert_eq!((vertexes[1].x, vertexes[2].x, vertexes[3].x), (0, 1, 1));
+        assert_eq!((vertexes[70000].x, vertexes[70000].id), (17500, 70000));
+    }
+
+    #[test]
+    fn compressed_nodes_are_inflated_no_further_than_their_counts() {
+        let compress = |body: &[u8]| {
+            let mut encoder = flate2::write::ZlibEncoder::new(b"ZNOD".to_vec(), flate2::Compression::default());
+            encoder.write_all(body).unwrap();
+            encoder.finish().unwrap()
+        };
+        let nodes = ExtendedNodes::new(&compress(&xnod_body(2)), &VERTEXES, &[]).unwrap();
+        assert_eq!(nodes.vertexes.len(), 3);
+        assert_eq!(nodes.segments.len(), 1);
+
+        // Trailing data is never inflated and a count larger than the data is an error
+        let mut body = xnod_body(2);
+        body.extend(vec![0; 1 << 20]);
+        assert!(ExtendedNodes::new(&compress(&body), &VERTEXES, &[]).is_ok());
+        let mut body = xnod_body(2);
+        body[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(ExtendedNodes::new(&compress(&body), &VERTEXES, &[]), Err(Error::Decoding(_, reason)) if reason.contains("need")));
+    }
+
+    #[test]
+    fn xnod_nodes_are_read_from_znodes() {
+        let lumps = with_lump(square_room(), "SEGS", RawData::new());
+        let lumps = with_lump(lumps, "SSECTORS", RawData::new());
+        let mut lumps = with_lump(lumps, "NODES", RawData::new());
+        lumps.insert(lumps.len() - 2, ("ZNODES", xnod_nodes()));
+        let map = map(&lumps).unwrap();
+        assert_eq!(map.nodes.len(), 1);
+        assert_eq!(map.sub_sectors.len(), 2);
+        assert_eq!(map.segments.iter().map(|segment| segment.end_verext_id).collect::<Vec<_>>(), [1, 0, 2, 3]);
+        assert_eq!(wad(&lumps).validate(), Problems::new());
+    }
+}
@@ -0,0 +1,159 @@
+use super::*;
+
+use std::fmt::{self, Display};
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// Doom's 16.16 fixed point number, the upper 16 bits are the whole map units and the
+/// lower 16 the fraction. Multiplication and division behave like vanilla's `FixedMul`
+/// and `FixedDiv`, everything else wraps like the C it replaces
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Fixed(pub i32);
+
+impl Fixed {
+    pub const FRAC_BITS: u32 = 16;
+    pub const ZERO: Self = Self(0);
+    pub const ONE: Self = Self(1 << Self::FRAC_BITS);
+    pub const MAX: Self = Self(i32::MAX);
+    pub const MIN: Self = Self(i32::MIN);
+
+    pub const fn from_int(value: i32) -> Self {
+        Self(value << Self::FRAC_BITS)
+    }
+
+    /// The whole part, rounded down like vanilla's `>> FRACBITS`
+    pub const fn to_int(self) -> i32 {
+        self.0 >> Self::FRAC_BITS
+    }
+
+    /// The nearest whole number, halves round up
+    pub const fn round(self) -> i32 {
+        ((self.0 as i64 + (1 << (Self::FRAC_BITS - 1))) >> Self::FRAC_BITS) as i32
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        Self((value * Self::ONE.0 as f64).round() as i32)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / Self::ONE.0 as f64
+    }
+
+    pub fn abs(self) -> Self {
+        Self(self.0.wrapping_abs())
+    }
+}
+
+impl From<i16> for Fixed {
+    fn from(value: i16) -> Self {
+        Self::from_int(value as i32)
+    }
+}
+
+impl Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_f64())
+    }
+}
+
+impl Add for Fixed {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0.wrapping_add(rhs.0))
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0.wrapping_sub(rhs.0))
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(self.0.wrapping_neg())
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self(((self.0 as i64 * rhs.0 as i64) >> Self::FRAC_BITS) as i32)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Self;
+
+    /// Results too large to represent saturate rather than overflow, as in vanilla
+    fn div(self, rhs: Self) -> Self {
+        if (self.0.unsigned_abs() >> 14) >= rhs.0.unsigned_abs() {
+            if (self.0 ^ rhs.0) < 0 { Self::MIN } else { Self::MAX }
+        } else {
+            Self((((self.0 as i64) << Self::FRAC_BITS) / rhs.0 as i64) as i32)
+        }
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl MulAssign for Fixed {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl DivAssign for Fixed {
+    fn div_assign(&mut self, rhs: Self) {
+        *self = *self / rhs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiplication_keeps_the_fraction() {
+        assert_eq!(Fixed::from_int(3) * Fixed::from_f64(0.5), Fixed::from_f64(1.5));
+        assert_eq!(Fixed::from_int(-3) * Fixed::from_f64(0.5), Fixed::from_f64(-1.5));
+    }
+
+    #[test]
+    fn multiplication_overflow_wraps_like_vanilla() {
+        assert_eq!(Fixed::MAX * Fixed::from_int(2), Fixed(-2));
+        assert_eq!(Fixed::from_int(128) * Fixed::from_int(256), Fixed::MIN);
+    }
+
+    #[test]
+    fn division_keeps_the_fraction() {
+        assert_eq!(Fixed::from_int(3) / Fixed::from_int(2), Fixed::from_f64(1.5));
+        assert_eq!(Fixed::from_int(-3) / Fixed::from_int(2), Fixed::from_f64(-1.5));
+    }
+
+    #[test]
+    fn division_overflow_saturates_like_vanilla() {
+        assert_eq!(Fixed::ONE / Fixed::ZERO, Fixed::MAX);
+        assert_eq!(-Fixed::ONE / Fixed::ZERO, Fixed::MIN);
+        // Vanilla gives up once the dividend is 2^14 times the divisor, even though
+        // 16384 itself would fit
+        assert_eq!(Fixed::from_int(16384) / Fixed::ONE, Fixed::MAX);
+        assert_eq!(Fixed::from_int(-16384) / Fixed::ONE, Fixed::MIN);
+        assert_eq!(Fixed::from_int(16383) / Fixed::ONE, Fixed::from_int(16383));
+    }
+}
@@ -0,0 +1,67 @@
+use super::*;
+
+/// A floor or ceiling texture, 64x64 palette indexes stored row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flat {
+    pub pixels: Vec<u8>,
+}
+
+impl Flat {
+    pub const SIZE: usize = 64;
+
+    /// Decode a flat lump, some ports pad flats out so anything past the 4096 pixels is ignored
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let pixels = data.get(..Self::SIZE * Self::SIZE).ok_or_else(|| Error::Decoding(
+            "flat".to_string(),
+            format!("{} bytes is too short for a {}x{} flat", data.len(), Self::SIZE, Self::SIZE)
+        ))?;
+        Ok(Self {
+            pixels: pixels.to_vec(),
+        })
+    }
+
+    /// Flats tile, so any coordinate is valid
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[(y % Self::SIZE) * Self::SIZE + x % Self::SIZE]
+    }
+
+    /// Convert to RGBA bytes row by row, flats are always opaque
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.pixels.iter().flat_map(|index| palette.rgb(*index).to_rgba(255)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each pixel's index is its row plus its column
+    fn flat_data() -> Vec<u8> {
+        (0..Flat::SIZE).flat_map(|y| (0..Flat::SIZE).map(move |x| (x + y) as u8)).collect()
+    }
+
+    #[test]
+    fn pixels_are_stored_row_by_row_and_tile() {
+        let flat = Flat::new(&flat_data()).unwrap();
+        assert_eq!(flat.pixel(3, 0), 3);
+        assert_eq!(flat.pixel(0, 5), 5);
+        assert_eq!(flat.pixel(63, 63), 126);
+        assert_eq!(flat.pixel(64 + 3, 128 + 5), 8);
+    }
+
+    #[test]
+    fn padding_is_ignored_but_short_flats_are_errors() {
+        let mut data = flat_data();
+        data.extend([0xFF; 64]);
+        assert_eq!(Flat::new(&data).unwrap(), Flat::new(&flat_data()).unwrap());
+        assert!(matches!(Flat::new(&data[..4095]), Err(Error::Decoding(..))));
+    }
+
+    #[test]
+    fn rgba_is_opaque() {
+        let palette = Palette { colours: std::array::from_fn(|i| Rgb { r: i as u8, g: 0, b: 255 - i as u8 }) };
+        let rgba = Flat::new(&flat_data()).unwrap().to_rgba(&palette);
+        assert_eq!(rgba.len(), Flat::SIZE * Flat::SIZE * 4);
+        assert_eq!(rgba[4 * 65..4 * 66], [2, 0, 253, 255]);
+    }
+}
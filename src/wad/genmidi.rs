@@ -0,0 +1,186 @@
+use super::*;
+use super::lumps::name_from_bytes;
+
+/// OPL2 register values for one operator
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
+pub struct OplOperator {
+    /// Register 0x20, tremolo, vibrato, sustain, KSR and frequency multiplier
+    pub characteristic: u8,
+    /// Register 0x60
+    pub attack_decay: u8,
+    /// Register 0x80
+    pub sustain_release: u8,
+    /// Register 0xE0
+    pub waveform: u8,
+    /// Top two bits of register 0x40
+    pub key_scale: u8,
+    /// Bottom six bits of register 0x40
+    pub output_level: u8,
+}
+
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
+#[brw(little)]
+pub struct OplVoice {
+    pub modulator: OplOperator,
+    /// Register 0xC0, feedback and connection
+    pub feedback: u8,
+    pub carrier: OplOperator,
+    pub unused: u8,
+    pub base_note_offset: i16,
+}
+
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Clone)]
+#[brw(little)]
+pub struct GenMidiInstrument {
+    pub flags: u16,
+    pub fine_tuning: u8,
+    pub fixed_note: u8,
+    pub voices: [OplVoice; 2],
+}
+
+impl GenMidiInstrument {
+    pub const FIXED_PITCH: u16 = 0x0001;
+    pub const DELAYED_VIBRATO: u16 = 0x0002;
+    pub const DOUBLE_VOICE: u16 = 0x0004;
+
+    /// Always plays `fixed_note` whatever note was asked for (most percussion)
+    pub fn is_fixed_pitch(&self) -> bool {
+        self.flags & Self::FIXED_PITCH != 0
+    }
+
+    /// Both voices are played, the second detuned by `fine_tuning`
+    pub fn is_double_voice(&self) -> bool {
+        self.flags & Self::DOUBLE_VOICE != 0
+    }
+}
+
+/// The GENMIDI lump (the same layout as an .op2 bank), instruments 0-127 are the General MIDI
+/// programs and 128-174 the percussion notes 35-81
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Clone)]
+#[brw(little, magic = b"#OPL_II#")]
+pub struct GenMidi {
+    #[br(parse_with = binrw::helpers::count(GenMidi::INSTRUMENT_COUNT))]
+    pub instruments: Vec<GenMidiInstrument>,
+    #[br(try_map = |names: [[u8; 32]; GenMidi::INSTRUMENT_COUNT]| names.into_iter().map(|x| name_from_bytes(x.to_vec())).collect())]
+    #[bw(map = |names: &Vec<String>| names.iter().map(|name| GenMidi::name_to_bytes(name)).collect::<Vec<_>>())]
+    pub names: Vec<String>,
+}
+
+impl GenMidi {
+    pub const INSTRUMENT_COUNT: usize = 175;
+    pub const MELODIC_COUNT: usize = 128;
+    pub const FIRST_PERCUSSION_NOTE: u8 = 35;
+    pub const LAST_PERCUSSION_NOTE: u8 = 81;
+
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        Self::read(&mut Cursor::new(data))
+            .map_err(|err| Error::Decoding("GENMIDI".to_string(), err.root_cause().to_string()))
+    }
+
+    fn name_to_bytes(name: &str) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (byte, c) in bytes.iter_mut().take(31).zip(name.bytes()) {
+            *byte = c;
+        }
+        bytes
+    }
+
+    /// The instrument for a General MIDI program (0-127)
+    pub fn melodic(&self, program: u8) -> Option<(&GenMidiInstrument, &str)> {
+        self.instrument(program as usize).filter(|_| (program as usize) < Self::MELODIC_COUNT)
+    }
+
+    /// The instrument for a note on the percussion channel (35-81)
+    pub fn percussion(&self, note: u8) -> Option<(&GenMidiInstrument, &str)> {
+        if !(Self::FIRST_PERCUSSION_NOTE..=Self::LAST_PERCUSSION_NOTE).contains(&note) {
+            return None;
+        }
+        self.instrument(Self::MELODIC_COUNT + (note - Self::FIRST_PERCUSSION_NOTE) as usize)
+    }
+
+    pub fn instrument(&self, index: usize) -> Option<(&GenMidiInstrument, &str)> {
+        Some((self.instruments.get(index)?, self.names.get(index)?.as_str()))
+    }
+
+    /// Serialize as an .op2 bank
+    pub fn to_op2(&self) -> Result<RawData, Error> {
+        let mut cursor = Cursor::new(RawData::new());
+        self.write(&mut cursor)
+            .map_err(|err| Error::Writing(err.root_cause().to_string()))?;
+        Ok(cursor.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Instrument `i` has `i` as its fixed note and first voice's base note offset and is named `INSTRUMENT i`,
+    /// every third is fixed pitch and every fifth double voice
+    fn gen_midi_data() -> RawData {
+        let mut data = b"#OPL_II#".to_vec();
+        for i in 0..GenMidi::INSTRUMENT_COUNT {
+            let flags = if i % 3 == 0 { GenMidiInstrument::FIXED_PITCH } else { 0 }
+                | if i % 5 == 0 { GenMidiInstrument::DOUBLE_VOICE } else { 0 };
+            data.extend(flags.to_le_bytes());
+            data.extend([128, i as u8]);
+            for voice in 0..2 {
+                data.extend([0x21, 0xF2, 0x54, 0, 0x40, 0x3F]);
+                data.push(0x0E);
+                data.extend([0x31, 0xF1, 0x53, 1, 0x80, 0x00]);
+                data.push(0);
+                data.extend((if voice == 0 { i as i16 } else { -12 }).to_le_bytes());
+            }
+        }
+        for i in 0..GenMidi::INSTRUMENT_COUNT {
+            data.extend(GenMidi::name_to_bytes(&format!("INSTRUMENT {i}")));
+        }
+        data
+    }
+
+    #[test]
+    fn every_instrument_and_name_is_read() {
+        let gen_midi = GenMidi::new(&gen_midi_data()).unwrap();
+        assert_eq!(gen_midi.instruments.len(), GenMidi::INSTRUMENT_COUNT);
+        assert_eq!(gen_midi.names.len(), GenMidi::INSTRUMENT_COUNT);
+
+        let (instrument, name) = gen_midi.instrument(30).unwrap();
+        assert_eq!(name, "INSTRUMENT 30");
+        assert!(instrument.is_fixed_pitch() && instrument.is_double_voice());
+        assert_eq!((instrument.fine_tuning, instrument.fixed_note), (128, 30));
+        assert_eq!(instrument.voices[0].modulator, OplOperator {
+            characteristic: 0x21, attack_decay: 0xF2, sustain_release: 0x54,
+            waveform: 0, key_scale: 0x40, output_level: 0x3F,
+        });
+        assert_eq!(instrument.voices[0].feedback, 0x0E);
+        assert_eq!(instrument.voices[0].carrier.waveform, 1);
+        assert_eq!(instrument.voices[0].base_note_offset, 30);
+        assert_eq!(instrument.voices[1].base_note_offset, -12);
+
+        let (instrument, _) = gen_midi.instrument(31).unwrap();
+        assert!(!instrument.is_fixed_pitch() && !instrument.is_double_voice());
+        assert_eq!(gen_midi.instrument(GenMidi::INSTRUMENT_COUNT), None);
+    }
+
+    #[test]
+    fn programs_and_percussion_notes_find_their_instruments() {
+        let gen_midi = GenMidi::new(&gen_midi_data()).unwrap();
+        assert_eq!(gen_midi.melodic(0).unwrap().1, "INSTRUMENT 0");
+        assert_eq!(gen_midi.melodic(127).unwrap().1, "INSTRUMENT 127");
+        assert_eq!(gen_midi.melodic(128), None);
+
+        assert_eq!(gen_midi.percussion(GenMidi::FIRST_PERCUSSION_NOTE).unwrap().1, "INSTRUMENT 128");
+        assert_eq!(gen_midi.percussion(GenMidi::LAST_PERCUSSION_NOTE).unwrap().1, "INSTRUMENT 174");
+        assert_eq!(gen_midi.percussion(34), None);
+        assert_eq!(gen_midi.percussion(82), None);
+    }
+
+    #[test]
+    fn op2_banks_are_written_as_they_were_read() {
+        let data = gen_midi_data();
+        assert_eq!(GenMidi::new(&data).unwrap().to_op2().unwrap(), data);
+
+        assert!(matches!(GenMidi::new(&data[..data.len() - 1]), Err(Error::Decoding(..))));
+        assert!(matches!(GenMidi::new(&[b"#OPL_I?#".as_slice(), &data[8..]].concat()), Err(Error::Decoding(..))));
+    }
+}
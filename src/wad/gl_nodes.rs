@@ -0,0 +1,293 @@
+use super::*;
+
+use binrw::{BinResult, Endian};
+
+/// The glBSP node format version, V1 has no magic and uses the map's own vertex format
+/// whereas later versions start GL_VERT with a magic and store 16.16 fixed point vertexes
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GlVersion {
+    V1,
+    V2,
+    V5,
+}
+
+impl GlVersion {
+    /// Work out the version from the start of the GL_VERT lump, V3 and V4 are rarely
+    /// seen in the wild and aren't supported
+    pub fn from_vertexes(data: &[u8]) -> Result<Self, Error> {
+        match data.get(0..4) {
+            Some(b"gNd2") => Ok(Self::V2),
+            Some(b"gNd5") => Ok(Self::V5),
+            Some(magic @ (b"gNd3" | b"gNd4")) => Err(GlNodes::error(format!(
+                "version {} GL nodes are not supported", magic[3] as char
+            ))),
+            _ => Ok(Self::V1),
+        }
+    }
+
+    fn magic_size(&self) -> usize {
+        match self {
+            Self::V1 => 0,
+            Self::V2 | Self::V5 => 4,
+        }
+    }
+
+    /// V5 widens indexes to 32 bits, which also moves the flag marking GL vertexes and
+    /// subsector children up to the top bit
+    fn is_wide(&self) -> bool {
+        *self == Self::V5
+    }
+
+    fn flag(&self) -> u32 {
+        if self.is_wide() { 1 << 31 } else { 1 << 15 }
+    }
+
+    fn none(&self) -> u32 {
+        if self.is_wide() { u32::MAX } else { u16::MAX as u32 }
+    }
+}
+
+/// A 16 or 32 bit index depending on the version
+#[binrw::parser(reader, endian)]
+fn gl_index(version: GlVersion) -> BinResult<u32> {
+    if version.is_wide() {
+        u32::read_options(reader, endian, ())
+    } else {
+        Ok(u16::read_options(reader, endian, ())? as u32)
+    }
+}
+
+#[binrw::parser(reader, endian)]
+fn gl_vertex_ref(version: GlVersion) -> BinResult<GlVertexRef> {
+    Ok(GlVertexRef::new(gl_index(reader, endian, (version,))?, version))
+}
+
+#[binrw::parser(reader, endian)]
+fn gl_child(version: GlVersion) -> BinResult<GlChild> {
+    Ok(GlChild::new(gl_index(reader, endian, (version,))?, version))
+}
+
+/// An index where all bits set means there isn't one
+#[binrw::parser(reader, endian)]
+fn gl_optional_index(version: GlVersion) -> BinResult<Option<usize>> {
+    let id = gl_index(reader, endian, (version,))?;
+    Ok((id != version.none()).then_some(id as usize))
+}
+
+/// V1 vertexes are whole map units, later versions are 16.16 fixed point
+#[binrw::parser(reader, endian)]
+fn gl_coordinate(version: GlVersion) -> BinResult<f64> {
+    match version {
+        GlVersion::V1 => Ok(i16::read_options(reader, endian, ())? as f64),
+        _ => Ok(i32::read_options(reader, endian, ())? as f64 / 65536.0),
+    }
+}
+
+#[derive(Debug, BinRead, PartialEq, Clone, Copy)]
+#[br(little, import(version: GlVersion))]
+pub struct GlVertex {
+    #[br(parse_with = gl_coordinate, args(version))]
+    pub x: f64,
+    #[br(parse_with = gl_coordinate, args(version))]
+    pub y: f64,
+}
+
+/// GL segs can run between the map's own vertexes or the extra vertexes glBSP added
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GlVertexRef {
+    Map(usize),
+    Gl(usize),
+}
+
+impl GlVertexRef {
+    fn new(id: u32, version: GlVersion) -> Self {
+        if id & version.flag() != 0 {
+            Self::Gl((id & !version.flag()) as usize)
+        } else {
+            Self::Map(id as usize)
+        }
+    }
+}
+
+#[derive(Debug, BinRead, PartialEq, Eq, Clone, Copy)]
+#[br(little, import(version: GlVersion))]
+pub struct GlSegment {
+    #[br(parse_with = gl_vertex_ref, args(version))]
+    pub start_vertex: GlVertexRef,
+    #[br(parse_with = gl_vertex_ref, args(version))]
+    pub end_vertex: GlVertexRef,
+    /// Minisegs run along a partition line rather than a linedef and have none
+    #[br(map = |id: u16| (id != u16::MAX).then_some(id))]
+    pub line_def_id: Option<u16>,
+    pub direction: SegDirection,
+    /// The seg on the other side of the same line, if there is one
+    #[br(parse_with = gl_optional_index, args(version))]
+    pub partner_segment_id: Option<usize>,
+}
+
+#[derive(Debug, BinRead, PartialEq, Eq, Clone, Copy)]
+#[br(little, import(version: GlVersion))]
+pub struct GlSubSector {
+    #[br(parse_with = gl_index, args(version))]
+    pub segments_count: u32,
+    #[br(parse_with = gl_index, args(version))]
+    pub first_segments_id: u32,
+}
+
+impl GlSubSector {
+    pub fn to_range(self) -> core::ops::Range<usize> {
+        (self.first_segments_id as usize)..(self.first_segments_id as usize + self.segments_count as usize)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GlChild {
+    Node(usize),
+    SubSector(usize),
+}
+
+impl GlChild {
+    fn new(id: u32, version: GlVersion) -> Self {
+        if id & version.flag() != 0 {
+            Self::SubSector((id & !version.flag()) as usize)
+        } else {
+            Self::Node(id as usize)
+        }
+    }
+}
+
+#[derive(Debug, BinRead, PartialEq, Eq, Clone, Copy)]
+#[br(little, import(version: GlVersion))]
+pub struct GlNode {
+    pub x_partion: i16,
+    pub y_partion: i16,
+    pub dx_partion: i16,
+    pub dy_partion: i16,
+    pub front_bbox: BoundingBox,
+    pub back_bbox: BoundingBox,
+    #[br(parse_with = gl_child, args(version))]
+    pub front_child: GlChild,
+    #[br(parse_with = gl_child, args(version))]
+    pub back_child: GlChild,
+}
+
+impl GlNode {
+    pub fn is_in_back_side(&self, (x, y): (f64, f64)) -> bool {
+        let dx = x - self.x_partion as f64;
+        let dy = y - self.y_partion as f64;
+        dx * self.dy_partion as f64 - dy * self.dx_partion as f64 <= 0.0
+    }
+}
+
+/// The GL_VERT, GL_SEGS, GL_SSECT and GL_NODES lumps built by glBSP, unlike the map's own
+/// nodes every subsector's segs form a closed polygon
+#[derive(Debug, PartialEq, Clone)]
+pub struct GlNodes {
+    pub version: GlVersion,
+    pub vertexes: Vec<GlVertex>,
+    pub segments: Vec<GlSegment>,
+    pub sub_sectors: Vec<GlSubSector>,
+    pub nodes: Vec<GlNode>,
+}
+
+impl GlNodes {
+    pub fn new(vertexes: &[u8], segments: &[u8], sub_sectors: &[u8], nodes: &[u8]) -> Result<Self, Error> {
+        let version = GlVersion::from_vertexes(vertexes)?;
+        Ok(Self {
+            version,
+            vertexes: Self::records("GL_VERT", &vertexes[version.magic_size()..], version)?,
+            segments: Self::records("GL_SEGS", segments, version)?,
+            sub_sectors: Self::records("GL_SSECT", sub_sectors, version)?,
+            nodes: Self::records("GL_NODES", nodes, version)?,
+        })
+    }
+
+    fn records<T>(lump: &str, data: &[u8], version: GlVersion) -> Result<Vec<T>, Error>
+    where
+        T: for<'a> BinRead<Args<'a> = (GlVersion,)>,
+    {
+        let mut cursor = Cursor::new(data);
+        let mut records = Vec::new();
+        while (cursor.position() as usize) < data.len() {
+            records.push(T::read_options(&mut cursor, Endian::Little, (version,))
+                .map_err(|err| Self::error(format!("{lump} record {}: {}", records.len(), err.root_cause())))?);
+        }
+        Ok(records)
+    }
+
+    fn error(reason: String) -> Error {
+        Error::Decoding("GL nodes".to_string(), reason)
+    }
+
+    /// The position of a seg's vertex, which may be one of the map's vertexes
+    pub fn vertex(&self, map_vertexes: &[Vertex], vertex: GlVertexRef) -> Option<(f64, f64)> {
+        match vertex {
+            GlVertexRef::Map(id) => map_vertexes.get(id).map(|v| (v.x as f64, v.y as f64)),
+            GlVertexRef::Gl(id) => self.vertexes.get(id).map(|v| (v.x, v.y)),
+        }
+    }
+
+    /// The corners of a subsector's polygon in order, for triangulating floors and ceilings
+    pub fn sub_sector_polygon(&self, map_vertexes: &[Vertex], sub_sector_id: usize) -> Option<Vec<(f64, f64)>> {
+        let sub_sector = self.sub_sectors.get(sub_sector_id)?;
+        self.segments.get(sub_sector.to_range())?.iter()
+            .map(|segment| self.vertex(map_vertexes, segment.start_vertex))
+            .collect()
+    }
+
+    /// The subsector containing a point, found by walking down from the root node
+    pub fn sub_sector_at(&self, point: (f64, f64)) -> Option<usize> {
+        let mut child = match self.nodes.len() {
+            0 => GlChild::SubSector(0),
+            count => GlChild::Node(count - 1),
+        };
+        // A well formed tree can't be deeper than it has nodes, this stops a malformed one looping
+        for _ in 0..=self.nodes.len() {
+            match child {
+                GlChild::SubSector(id) => return (id < self.sub_sectors.len()).then_some(id),
+                GlChild::Node(id) => {
+                    let node = self.nodes.get(id)?;
+                    child = if node.is_in_back_side(point) { node.back_child } else { node.front_child };
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_maps::*;
+
+    fn with_gl_nodes(vertexes: &[u8], segments: RawData) -> TestLumps {
+        let mut lumps = square_room();
+        lumps.extend([
+            ("GL_VERT", vertexes.to_vec()),
+            ("GL_SEGS", segments),
+            ("GL_SSECT", RawData::new()),
+            ("GL_NODES", RawData::new()),
+        ]);
+        lumps
+    }
+
+    #[test]
+    fn versions_come_from_the_vertex_magic() {
+        assert_eq!(GlVersion::from_vertexes(b"gNd2").ok(), Some(GlVersion::V2));
+        assert_eq!(GlVersion::from_vertexes(b"gNd5").ok(), Some(GlVersion::V5));
+        assert_eq!(GlVersion::from_vertexes(&[0, 0, 0, 0]).ok(), Some(GlVersion::V1));
+        assert!(GlVersion::from_vertexes(b"gNd3").is_err());
+    }
+
+    #[test]
+    fn maps_load_with_their_gl_nodes() {
+        let map = map(&with_gl_nodes(b"gNd2", RawData::new())).unwrap();
+        assert_eq!(map.gl_nodes.map(|gl_nodes| gl_nodes.version), Some(GlVersion::V2));
+    }
+
+    #[test]
+    fn maps_load_without_gl_nodes_that_cant_be_read() {
+        assert_eq!(map(&with_gl_nodes(b"gNd3", RawData::new())).unwrap().gl_nodes, None);
+        assert_eq!(map(&with_gl_nodes(b"gNd2", vec![0, 0, 0])).unwrap().gl_nodes, None);
+    }
+}
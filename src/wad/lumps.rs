@@ -7,14 +7,20 @@ use std::convert::{TryFrom, From};
 use std::fmt::{self, Display};
 
 use modular_bitfield::prelude::*;
-use binrw::{binrw, args, NamedArgs};
-use regex::Regex;
+use binrw::{binrw, args, NamedArgs, BinWrite};
 pub use binrw::BinRead;
 
 #[derive(Debug)]
 pub enum Error {
     Unwraping(String, String),
     Access(String),
+    Deserialize {
+        lump: String,
+        index: usize,
+        offset: usize,
+        expected_size: usize,
+        reason: String,
+    },
 }
 
 impl  Display for Error  {
@@ -22,80 +28,104 @@ impl  Display for Error  {
         match self {
             Self::Unwraping(outer, inner) => write!(f, "couldn't unwrap'{outer}' to inner `{inner}`"),
             Self::Access(message) => write!(f, "error accessing LumpData: `{message}`"),
+            Self::Deserialize { lump, index, offset, expected_size, reason } => write!(
+                f,
+                "couldn't deserialize lump '{lump}' (index {index}, offset {offset}, expected {expected_size} bytes): `{reason}`"
+            ),
         }
     }
 }
 
-pub static mut LUMPMETA: OnceLock<HashMap<&str, usize>> = OnceLock::new();
-
-
-pub fn lump_meta() -> &'static mut HashMap<&'static str, usize> {
-    unsafe {
-        LUMPMETA.get_or_init(|| HashMap::new() );
-        LUMPMETA.get_mut().unwrap()
-    }
-}
-
-
 #[derive(Debug, BinRead)]
 #[br(little)]
 pub struct Lump {
     pub offset: i32,
     pub size: i32,
-    #[br(count = 8, map = |x: Vec<u8>| String::from_utf8(x).unwrap())]
+    #[br(count = 8, try_map = |x: Vec<u8>| name_from_bytes(x))]
     pub name: String,
-    #[br(calc = (&name, size).into())]
+    /// Set along with `count` by `LumpKind::classify` once the whole directory has been read
+    #[br(ignore)]
     pub kind: LumpKind,
-    #[br(calc = Self::lump_count(&kind, size))]
+    #[br(ignore)]
     pub count: usize, 
+    /// Position of the lump within the WAD directory, set by `WadMeta::new`
+    #[br(ignore)]
+    pub index: usize,
 }
 
 impl Lump {
-    pub fn deserialize<T:for<'a> BinRead<Args<'a>=()> + 'static>(&self, raw_data: &super::RawData) -> Vec<T> {
-        let mut cursor = Cursor::new(raw_data);
-        cursor.seek(SeekFrom::Start(self.offset as u64)).unwrap();
-        
-        let result = <Vec<T> as BinRead>::read_le_args(&mut cursor, args! { count: self.count }).unwrap();
-        self.post_process();
-        result
+    /// Deserialize every record in the lump, each record is given its index within the lump as its id
+    pub fn deserialize<T: for<'a> BinRead<Args<'a> = (u16,)>>(&self, raw_data: &super::RawData) -> Result<Vec<T>, Error> {
+        self.deserialize_with(raw_data, Self::record_size(&self.kind), |id| (id,))
     }
 
-    fn lump_count(kind: &LumpKind, size: i32) -> usize {
-        if size == 0 { 0 } else {
-            size as usize / (match kind {
-                LumpKind::Things => 10,
-                LumpKind::LineDefs => 14,
-                LumpKind::SideDefs => 30,
-                LumpKind::Vertexs => 4,
-                LumpKind::Segments => 12,
-                LumpKind::SubSectors => 4,
-                LumpKind::Nodes => 28,
-                LumpKind::Sectors => 26,
-                LumpKind::Rejects => 1,
-                LumpKind::BlockMaps => 8,
-                LumpKind::BlockMapOffset=> 2,
-                _ => 1,
-            } as usize)
+    /// Deserialize the lump's records of `record_size` bytes, for records whose size depends
+    /// on the map's format. `args` builds the binrw args for the record with the given id,
+    /// this is how per-parse context (like a map's sector count) reaches a record
+    pub fn deserialize_with<T, A>(&self, raw_data: &super::RawData, record_size: usize, args: impl Fn(u16) -> A) -> Result<Vec<T>, Error>
+    where
+        T: for<'a> BinRead<Args<'a> = A>,
+    {
+        let count = self.size.max(0) as usize / record_size;
+        let expected_size = count * record_size;
+        let mut cursor = Cursor::new(self.data(raw_data)?);
+
+        (0..count).map(|id| {
+            // Records keep their id in 16 bits, there's no id to give any past that
+            let id = u16::try_from(id)
+                .map_err(|_| self.deserialize_error(expected_size, format!("record {id} is past the last record id, {}", u16::MAX)))?;
+            T::read_le_args(&mut cursor, args(id))
+                .map_err(|err| self.deserialize_error(expected_size, err.root_cause().to_string()))
+        }).collect()
+    }
+
+    /// The lump's bytes within the WAD's raw data
+    pub fn data<'d>(&self, raw_data: &'d super::RawData) -> Result<&'d [u8], Error> {
+        let (offset, size) = (self.offset as usize, self.size as usize);
+        if self.offset < 0 || self.size < 0 || offset + size > raw_data.len() {
+            return Err(self.deserialize_error(size, format!(
+                "lump of {} bytes lies outside of the {} bytes of wad data", self.size, raw_data.len()
+            )));
         }
+        Ok(&raw_data[offset..offset + size])
     }
 
-    fn post_process(&self) {
-        match self.kind {
-            LumpKind::Sectors => lump_meta().insert("SECTOR_COUNT", self.count),
-            LumpKind::Rejects => lump_meta().insert("SECTOR_COUNT", 0),
-            _ => None, 
-        };
-        lump_meta().insert("ID", 0);
+    fn deserialize_error(&self, expected_size: usize, reason: String) -> Error {
+        Error::Deserialize {
+            lump: self.name.clone(),
+            index: self.index,
+            offset: self.offset as usize,
+            expected_size,
+            reason,
+        }
     }
-}
 
-fn get_and_incriment_id() -> u16 {
-    let id = lump_meta().entry("ID").or_insert(0); 
-    lump_meta().insert("ID", *id + 1);
-    *id as u16
+    fn lump_count(kind: &LumpKind, size: i32) -> usize {
+        if size <= 0 { 0 } else {
+            size as usize / Self::record_size(kind)
+        }
+    }
+
+    /// Size in bytes of a single record of the given kind
+    fn record_size(kind: &LumpKind) -> usize {
+        match kind {
+            LumpKind::Things => 10,
+            LumpKind::LineDefs => 14,
+            LumpKind::SideDefs => 30,
+            LumpKind::Vertexs => 4,
+            LumpKind::Segments => 12,
+            LumpKind::SubSectors => 4,
+            LumpKind::Nodes => 28,
+            LumpKind::Sectors => 26,
+            LumpKind::Rejects => 1,
+            LumpKind::BlockMaps => 8,
+            LumpKind::BlockMapOffset=> 2,
+            _ => 1,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Ord, Eq)]
+#[derive(Debug, PartialEq, PartialOrd, Ord, Eq, Clone, Copy, Default)]
 pub enum LumpKind {
     Pallet,
     ColourMap,
@@ -124,169 +154,370 @@ pub enum LumpKind {
     Graphics,
     StartMarker,
     EndMarker,
+    Sprite,
+    Flat,
+    Patch,
+    Behavior,
+    Scripts,
+    TextMap,
+    ZNodes,
+    Dialogue,
+    EndMap,
+    GlMarker,
+    GlVertexs,
+    GlSegments,
+    GlSubSectors,
+    GlNodes,
+    GlPvs,
+    MapInfo,
+    SoundInfo,
+    AnimDefs,
+    Animated,
+    Switches,
+    #[default]
+    Data,
 }
 
-impl From<(&String, i32)> for LumpKind {
-    fn from((name, size): (&String, i32)) -> Self {
-        let lump_kind_matcher = Regex::new(
-            r#"(?x)
-            (PLAYPAL) |
-            (COLORMAP) |
-            (ENDOOM)|
-            (DEMO)|
-            (THINGS)|
-            (LINEDEFS)|
-            (SIDEDEFS)|
-            (VERTEXES)|
-            (SEGS)|
-            (SSECTORS)|
-            (NODES)|
-            (SECTORS)|
-            (REJECT)|
-            (BLOCKMAP)|
-            (TEXTURE)|
-            (PNAMES)|
-            (GENMIDI)|
-            (DMXGUS)|
-            (DP)|
-            (DS)|
-            (D_)|
-            (_START)|
-            (_END)\s+(\d+)
-            "#).unwrap();
-        let captures = lump_kind_matcher.captures(name).map(|captures| {
-            captures
-                .iter() // All the captured groups
-                .skip(1) // Skipping the complete match
-                .flat_map(|c| c) // Ignoring all empty optional matches
-                .map(|c| c.as_str()) // Grab the original strings
-                .collect::<Vec<_>>() // Create a vector
-        });
-    
-        match captures.as_ref().map(|c| c.as_slice()) {
-            Some(["PLAYPAL"]) => Self::Pallet,
-            Some(["COLORMAP"]) => Self::ColourMap,
-            Some(["ENDOOM"]) => Self::AnsiText,
-            Some(["DEMO"]) => Self::Demo,
-            Some(["THINGS"]) => Self::Things,
-            Some(["LINEDEFS"]) => Self::LineDefs,
-            Some(["SIDEDEFS"]) => Self::SideDefs,
-            Some(["VERTEXES"]) => Self::Vertexs,
-            Some(["SEGS"]) => Self::Segments,
-            Some(["SSECTORS"]) => Self::SubSectors,
-            Some(["NODES"]) => Self::Nodes,
-            Some(["SECTORS"]) => Self::Sectors,
-            Some(["REJECT"]) => Self::Rejects,
-            Some(["BLOCKMAP"]) => Self::BlockMaps,
-            Some(["TEXTURE"]) => Self::Texture,
-            Some(["PNAMES"]) => Self::PNames,
-            Some(["GENMIDI"]) => Self::GenMidi,
-            Some(["DMXGUS"]) => Self::Dmxgus,
-            Some(["DP"]) => Self::SoundSpeaker,
-            Some(["DS"]) => Self::SoundDoomFormat,
-            Some(["D_"]) => Self::Music,
-            Some(["_START"]) => Self::StartMarker,
-            Some(["_END"]) => Self::EndMarker,
-            _ => if size > 0 { Self::Graphics } else { Self::MapMarker }
+impl LumpKind {
+    /// Classify every lump in a directory, lumps within a map or between namespace markers
+    /// are classified by where they are rather than by what they're called
+    pub fn classify(lumps: &mut Lumps, raw_data: &super::RawData) {
+        let mut namespace: Option<MarkedNamespace> = None;
+        let mut map_end = 0;
+        for i in 0..lumps.len() {
+            let name = lumps[i].name.to_ascii_uppercase();
+            let kind = if i < map_end {
+                Self::from_map_lump_name(&name)
+            } else if let Some(current) = namespace {
+                if current.is_end_marker(&name) {
+                    namespace = None;
+                    Self::EndMarker
+                } else if MarkedNamespace::is_sub_marker(&name, &lumps[i]) {
+                    if name.ends_with("_START") { Self::StartMarker } else { Self::EndMarker }
+                } else {
+                    match current {
+                        MarkedNamespace::Sprites => Self::Sprite,
+                        MarkedNamespace::Flats => Self::Flat,
+                        MarkedNamespace::Patches => Self::Patch,
+                        // Whole images in formats that aren't decoded
+                        MarkedNamespace::Textures => Self::Data,
+                    }
+                }
+            } else if let Some(start) = MarkedNamespace::from_start_marker(&name) {
+                namespace = Some(start);
+                Self::StartMarker
+            } else if Map::is_marker(lumps, i) {
+                map_end = Map::map_end(lumps, i);
+                Self::MapMarker
+            } else {
+                Self::from_name_and_data(&name, lumps[i].data(raw_data).unwrap_or_default())
+            };
+            lumps[i].count = Lump::lump_count(&kind, lumps[i].size);
+            lumps[i].kind = kind;
+        }
+    }
+
+    /// Lumps following a map marker
+    fn from_map_lump_name(name: &str) -> Self {
+        match name {
+            "THINGS" => Self::Things,
+            "LINEDEFS" => Self::LineDefs,
+            "SIDEDEFS" => Self::SideDefs,
+            "VERTEXES" => Self::Vertexs,
+            "SEGS" => Self::Segments,
+            "SSECTORS" => Self::SubSectors,
+            "NODES" => Self::Nodes,
+            "SECTORS" => Self::Sectors,
+            "REJECT" => Self::Rejects,
+            "BLOCKMAP" => Self::BlockMaps,
+            "BEHAVIOR" => Self::Behavior,
+            "SCRIPTS" => Self::Scripts,
+            "TEXTMAP" => Self::TextMap,
+            "ZNODES" => Self::ZNodes,
+            "DIALOGUE" => Self::Dialogue,
+            "ENDMAP" => Self::EndMap,
+            "GL_VERT" => Self::GlVertexs,
+            "GL_SEGS" => Self::GlSegments,
+            "GL_SSECT" => Self::GlSubSectors,
+            "GL_NODES" => Self::GlNodes,
+            "GL_PVS" => Self::GlPvs,
+            _ if name.starts_with("GL_") => Self::GlMarker,
+            _ => Self::Data,
+        }
+    }
+
+    /// Lumps outside of maps and namespaces, known names first then sniffing the data
+    fn from_name_and_data(name: &str, data: &[u8]) -> Self {
+        let has_digits_after = |prefix: &str| name.strip_prefix(prefix)
+            .is_some_and(|rest| !rest.is_empty() && rest.bytes().all(|b| b.is_ascii_digit()));
+        let is_dmx_sound = data.len() >= 8 && data[0..2] == [3, 0]
+            && u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize + 8 <= data.len();
+
+        match name {
+            "PLAYPAL" => Self::Pallet,
+            "COLORMAP" => Self::ColourMap,
+            // Doom, Heretic and Strife's quit screens
+            "ENDOOM" | "ENDTEXT" | "ENDSTRF" => Self::AnsiText,
+            "TEXTURE1" | "TEXTURE2" => Self::Texture,
+            "PNAMES" => Self::PNames,
+            "GENMIDI" => Self::GenMidi,
+            // Doom II ships DMXGUSC alongside DMXGUS
+            "DMXGUS" | "DMXGUSC" => Self::Dmxgus,
+            // Hexen and ZDoom
+            "MAPINFO" | "ZMAPINFO" => Self::MapInfo,
+            "SNDINFO" => Self::SoundInfo,
+            "ANIMDEFS" => Self::AnimDefs,
+            // Boom
+            "ANIMATED" => Self::Animated,
+            "SWITCHES" => Self::Switches,
+            _ if has_digits_after("DEMO") => Self::Demo,
+            _ if data.is_empty() && name.ends_with("_START") => Self::StartMarker,
+            _ if data.is_empty() && name.ends_with("_END") => Self::EndMarker,
+            // Heretic's music is MUS_*, Hexen's doesn't follow any pattern
+            _ if data.starts_with(b"MUS\x1A") || data.starts_with(b"MThd") => Self::Music,
+            _ if name.starts_with("DS") && is_dmx_sound => Self::SoundDoomFormat,
+            _ if name.starts_with("DP") && data.starts_with(&[0, 0]) => Self::SoundSpeaker,
+            _ if Picture::is_valid_header(data) => Self::Graphics,
+            // Heretic and Hexen's sounds aren't prefixed
+            _ if is_dmx_sound => Self::SoundDoomFormat,
+            _ => Self::Data,
         }
     }
 }
 
-fn reject_count() -> usize {
-    let lump_meta = lump_meta();
-    let sec_count = lump_meta.get("SECTOR_COUNT").unwrap();
-    (sec_count * sec_count) / 8
+/// Names are NUL padded out to 8 bytes, anything after the first NUL is padding
+/// (or garbage left behind by some editors)
+pub fn name_from_bytes(mut bytes: Vec<u8>) -> Result<String, std::string::FromUtf8Error> {
+    if let Some(end) = bytes.iter().position(|b| *b == 0) {
+        bytes.truncate(end);
+    }
+    String::from_utf8(bytes)
 }
 
 fn bytes_to_string(bytes: Vec<u8>) -> String {
     bytes.iter().map(|b| char::from(*b)).collect()
 }
 
+/// Inverse of `bytes_to_string`, NUL padded (or truncated) to 8 bytes
+pub fn string_to_bytes(string: &str) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for (byte, c) in bytes.iter_mut().zip(string.chars()) {
+        *byte = c as u8;
+    }
+    bytes
+}
 
-#[derive(Debug, BinRead, PartialEq, Eq, Copy, Clone)]
-#[br(little)]
+
+/// The binary layout of a map's THINGS and LINEDEFS, Hexen format maps are marked by
+/// having a BEHAVIOR lump
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum MapFormat {
+    #[default]
+    Doom,
+    Hexen,
+}
+
+impl MapFormat {
+    pub fn thing_size(&self) -> usize {
+        match self {
+            Self::Doom => 10,
+            Self::Hexen => 20,
+        }
+    }
+
+    pub fn line_def_size(&self) -> usize {
+        match self {
+            Self::Doom => 14,
+            Self::Hexen => 16,
+        }
+    }
+}
+
+/// Fields only Hexen format things have are left as 0 in Doom format maps
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
+#[brw(little)]
+#[br(import(id: u16, format: MapFormat))]
+#[bw(import(format: MapFormat))]
 pub struct Thing {
+    /// Id used by scripts and specials to refer to the thing
+    #[brw(if(format == MapFormat::Hexen))]
+    pub tid: i16,
     pub x: i16,
     pub y: i16,
+    /// Height above the floor
+    #[brw(if(format == MapFormat::Hexen))]
+    pub z: i16,
     pub angle_facing: i16,
     pub doomed_thing_type: i16,
+    /// Hexen reuses bit 4 to mark dormant things and adds class and game mode bits above it
     pub flags: ThingFlags,
-    #[br(calc = get_and_incriment_id())]
+    #[brw(if(format == MapFormat::Hexen))]
+    pub special: u8,
+    #[brw(if(format == MapFormat::Hexen))]
+    pub args: [u8; 5],
+    #[br(calc = id)]
+    #[bw(ignore)]
     pub id: u16,
 }
 
-#[derive(Debug, BinRead, PartialEq, Eq, Copy, Clone)]
-#[br(little)]
+/// Doom format linedefs have a 16 bit special and a tag, Hexen format ones have an 8 bit
+/// special and five args to pass to it
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
+#[brw(little)]
+#[br(import(id: u16, format: MapFormat))]
+#[bw(import(format: MapFormat))]
 pub struct LineDef {
     pub start_vertex_id: u16,
     pub end_vertex_id: u16,
     pub flags: LineDefFlags,
+    #[br(parse_with = LineDef::read_special, args(format))]
+    #[bw(write_with = LineDef::write_special, args(format))]
     pub special_type: i16,
+    #[brw(if(format == MapFormat::Doom))]
     pub tag: i16,
+    #[brw(if(format == MapFormat::Hexen))]
+    pub args: [u8; 5],
     pub front: i16,
     pub back: i16,
-    #[br(calc = get_and_incriment_id())]
+    #[br(calc = id)]
+    #[bw(ignore)]
     pub id: u16,
 }
 
-#[derive(Debug, BinRead, PartialEq, Eq)]
-#[br(little)]
+impl LineDef {
+    #[binrw::parser(reader, endian)]
+    fn read_special(format: MapFormat) -> binrw::BinResult<i16> {
+        match format {
+            MapFormat::Doom => i16::read_options(reader, endian, ()),
+            MapFormat::Hexen => Ok(u8::read_options(reader, endian, ())? as i16),
+        }
+    }
+
+    /// Hexen's specials are a single byte, any larger can't be written in that format
+    #[binrw::writer(writer, endian)]
+    fn write_special(special_type: &i16, format: MapFormat) -> binrw::BinResult<()> {
+        match format {
+            MapFormat::Doom => special_type.write_options(writer, endian, ()),
+            MapFormat::Hexen => u8::try_from(*special_type)
+                .map_err(|_| binrw::Error::AssertFail {
+                    pos: writer.stream_position().unwrap_or_default(),
+                    message: format!("special {special_type} doesn't fit in a Hexen linedef"),
+                })?
+                .write_options(writer, endian, ()),
+        }
+    }
+}
+
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq)]
+#[brw(little)]
+#[br(import(id: u16))]
 pub struct SideDef {
     pub x_offset: i16,
     pub y_offset: i16,
     #[br(count = 8, map = |x: Vec<u8>| bytes_to_string(x))]
+    #[bw(map = |x: &String| string_to_bytes(x))]
     pub name_of_upper: String,
     #[br(count = 8, map = |x: Vec<u8>| bytes_to_string(x))]
+    #[bw(map = |x: &String| string_to_bytes(x))]
     pub name_of_lower: String,
     #[br(count = 8, map = |x: Vec<u8>| bytes_to_string(x))]
+    #[bw(map = |x: &String| string_to_bytes(x))]
     pub name_of_middle: String,
     pub sector_this_sidedef_faces: i16,
-    #[br(calc = get_and_incriment_id())]
+    #[br(calc = id)]
+    #[bw(ignore)]
     pub id: u16,
 }
 
-#[derive(Debug, BinRead, PartialEq, Eq, Copy, Clone)]
-#[br(little)]
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
+#[brw(little)]
+#[br(import(id: u16))]
 pub struct Vertex {
     pub x: i16,
     pub y: i16,
-    #[br(calc = get_and_incriment_id())]
-    pub id: u16,
+    // Extended nodes can add vertexes past what 16 bits can index
+    #[br(calc = id as u32)]
+    #[bw(ignore)]
+    pub id: u32,
 }
 
-#[derive(Debug, BinRead, PartialEq, Eq, Copy, Clone)]
-#[br(little)]
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
+#[brw(little)]
+#[br(import(id: u16))]
 pub struct Segment {
-    pub start_vertext_id: u16,
-    pub end_verext_id: u16,
-    pub angle: i16, // full circle is -32768 to 32767.
-    pub line_def_id: u16,
+    // Stored as 16 bits in SEGS but extended nodes can reference more vertexes than that
+    #[br(map = |x: u16| x as u32)]
+    #[bw(try_map = |x: &u32| u16::try_from(*x))]
+    pub start_vertext_id: u32,
+    #[br(map = |x: u16| x as u32)]
+    #[bw(try_map = |x: &u32| u16::try_from(*x))]
+    pub end_verext_id: u32,
+    // Only the upper 16 bits of the angle are stored
+    #[br(map = Angle::from_i16)]
+    #[bw(map = |x: &Angle| x.to_i16())]
+    pub angle: Angle,
+    /// None for minisegs, which run along a partition line rather than a linedef and are
+    /// stored with a linedef of 0xFFFF
+    #[br(map = Self::line_def_from_u16)]
+    #[bw(try_map = Self::line_def_to_u16)]
+    pub line_def_id: Option<u32>,
     pub direction: SegDirection,
     pub offset: i16, // distance along linedef to start of segments 
-    #[br(calc = get_and_incriment_id())]
-    pub id: u16,
+    #[br(calc = id as u32)]
+    #[bw(ignore)]
+    pub id: u32,
 }
 
-#[derive(Debug, BinRead, PartialEq, Eq, Copy, Clone)]
-#[br(little)]
+impl Segment {
+    const NO_LINE_DEF_16: u16 = 0xFFFF;
+
+    fn line_def_from_u16(line_def_id: u16) -> Option<u32> {
+        (line_def_id != Self::NO_LINE_DEF_16).then_some(line_def_id as u32)
+    }
+
+    fn line_def_to_u16(line_def_id: &Option<u32>) -> Result<u16, String> {
+        match line_def_id {
+            None => Ok(Self::NO_LINE_DEF_16),
+            Some(id) => u16::try_from(*id).ok().filter(|id| *id != Self::NO_LINE_DEF_16)
+                .ok_or_else(|| format!("linedef {id} doesn't fit in SEGS")),
+        }
+    }
+
+    /// The angle of the line from one point to another, rounded to what SEGS can store
+    pub fn angle_between((x1, y1): (f64, f64), (x2, y2): (f64, f64)) -> Angle {
+        Angle::from_i16(((y2 - y1).atan2(x2 - x1) / std::f64::consts::TAU * 65536.0).round() as i32 as i16)
+    }
+}
+
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
+#[brw(little)]
+#[br(import(id: u16))]
 pub struct SubSector {
-    pub segments_count: u16,
-    pub first_segments_id: u16,
-    #[br(calc = get_and_incriment_id())]
-    pub id: u16,
+    #[br(map = |x: u16| x as u32)]
+    #[bw(try_map = |x: &u32| u16::try_from(*x))]
+    pub segments_count: u32,
+    #[br(map = |x: u16| x as u32)]
+    #[bw(try_map = |x: &u32| u16::try_from(*x))]
+    pub first_segments_id: u32,
+    #[br(calc = id as u32)]
+    #[bw(ignore)]
+    pub id: u32,
 }
 
 impl SubSector {
-    pub const SUB_SECTOR_IDENTIFIER: u16 = 0x8000;
-    pub const IDENTIFIER_BITMASK: u16 = 0b0111111111111111;
+    /// Node children are widened to 32 bits when read so the flag marking a subsector
+    /// is always the top bit, as it is in the extended node formats
+    pub const SUB_SECTOR_IDENTIFIER: u32 = 0x8000_0000;
+    pub const IDENTIFIER_BITMASK: u32 = 0x7FFF_FFFF;
 
-    pub fn to_range(&self) -> core::ops::Range<usize> {
-        (self.first_segments_id as usize)..((self.first_segments_id + self.segments_count) as usize)
+    pub fn to_range(self) -> core::ops::Range<usize> {
+        (self.first_segments_id as usize)..(self.first_segments_id as usize + self.segments_count as usize)
     }
 }
 
-#[derive(Debug, BinRead, PartialEq, Eq, Copy, Clone)]
-#[br(little)]
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
+#[brw(little)]
+#[br(import(id: u16))]
 pub struct Node {
     pub x_partion: i16,
     pub y_partion: i16,
@@ -294,13 +525,36 @@ pub struct Node {
     pub dy_partion: i16, 
     pub front_bbox: BoundingBox,
     pub back_bbox: BoundingBox,
-    pub front_child_id: u16,
-    pub back_child_id: u16,
-    #[br(calc = get_and_incriment_id())]
-    pub id: u16,
+    #[br(map = Self::child_from_u16)]
+    #[bw(try_map = Self::child_to_u16)]
+    pub front_child_id: u32,
+    #[br(map = Self::child_from_u16)]
+    #[bw(try_map = Self::child_to_u16)]
+    pub back_child_id: u32,
+    #[br(calc = id as u32)]
+    #[bw(ignore)]
+    pub id: u32,
 }
 
 impl Node {
+    const SUB_SECTOR_IDENTIFIER_16: u16 = 0x8000;
+
+    /// Moves the subsector flag from bit 15 to bit 31
+    fn child_from_u16(child_id: u16) -> u32 {
+        if child_id & Self::SUB_SECTOR_IDENTIFIER_16 != 0 {
+            SubSector::SUB_SECTOR_IDENTIFIER | (child_id & !Self::SUB_SECTOR_IDENTIFIER_16) as u32
+        } else {
+            child_id as u32
+        }
+    }
+
+    fn child_to_u16(child_id: &u32) -> Result<u16, String> {
+        let id = child_id & SubSector::IDENTIFIER_BITMASK;
+        let id_16 = u16::try_from(id).ok()
+            .filter(|id| *id < Self::SUB_SECTOR_IDENTIFIER_16)
+            .ok_or_else(|| format!("child {id} doesn't fit in a 16 bit node"))?;
+        Ok(if child_id & SubSector::SUB_SECTOR_IDENTIFIER != 0 { id_16 | Self::SUB_SECTOR_IDENTIFIER_16 } else { id_16 })
+    }
 
     pub fn children_are_sub_sectors(&self) -> (bool, bool) {
         (
@@ -309,68 +563,44 @@ impl Node {
         )
     }
 
-    pub fn is_in_back_side(&self, (player_x, player_y): (i16, i16)) -> bool {
-        let dx = (player_x - self.x_partion) as i32;
-        let dy = (player_y - self.y_partion) as i32;
-        dx * self.dy_partion as i32 - dy * self.dx_partion as i32 <= 0
+    /// Widened to 64 bits before subtracting, points and partitions at opposite edges of
+    /// a map can be further apart than 16.16 fixed point can hold
+    pub fn is_in_back_side(&self, (x, y): (Fixed, Fixed)) -> bool {
+        let dx = x.0 as i64 - Fixed::from(self.x_partion).0 as i64;
+        let dy = y.0 as i64 - Fixed::from(self.y_partion).0 as i64;
+        dx * self.dy_partion as i64 - dy * self.dx_partion as i64 <= 0
     }
 
 }
 
-#[derive(Debug, BinRead, PartialEq, Eq)]
-#[br(little)]
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq)]
+#[brw(little)]
+#[br(import(id: u16))]
 pub struct Sector {
     pub floor_height: i16,
     pub ceiling_height: i16,
-    #[br(count = 8, map = |x: Vec<u8>| String::from_utf8(x).unwrap())]
+    #[br(count = 8, map = |x: Vec<u8>| bytes_to_string(x))]
+    #[bw(map = |x: &String| string_to_bytes(x))]
     pub name_of_floor_texture: String,
-    #[br(count = 8, map = |x: Vec<u8>| String::from_utf8(x).unwrap())]
+    #[br(count = 8, map = |x: Vec<u8>| bytes_to_string(x))]
+    #[bw(map = |x: &String| string_to_bytes(x))]
     pub name_of_ceiling_texture: String,
     pub light_level: i16,
     pub special_type: i16,
     pub tag: i16,
-    #[br(calc = get_and_incriment_id())]
-    pub id: u16,
-}
-#[derive(Debug, BinRead, PartialEq, Eq, Clone)]
-#[br(little)]
-pub struct Reject {
-    #[br(count = Self::reject_count())]
-    pub table: Vec<u8>,
-    #[br(calc = get_and_incriment_id())]
-    pub id: u16,
-}
-
-impl Reject {
-    pub fn reject_count() -> usize {
-        let lump_meta = lump_meta();
-        let sec_count = lump_meta.get("SECTOR_COUNT").unwrap();
-        (sec_count * sec_count) / 8
-    }
-}
-
-#[derive(Debug, BinRead, PartialEq, Eq)]
-#[br(little)]
-pub struct BlockMap {
-    pub x_grid_origin: i16,
-    pub y_grid_origin: i16,
-    pub columns: i16,
-    pub rows: i16,
-    #[br(calc = get_and_incriment_id())]
+    #[br(calc = id)]
+    #[bw(ignore)]
     pub id: u16,
-    //#[br(count = (columns as usize * rows as usize))]
-    //pub offsets: Vec<i16>
 }
-
-#[derive(Debug, BinRead, PartialEq, Eq, Copy, Clone)]
-#[br(little,  repr = i16)]
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
+#[brw(little, repr = i16)]
 pub enum SegDirection {
     SameAsLineDef = 0,
     OppositOfLineDef = 1,
 }
 
 // top, bottom, left and righ
-#[derive(Debug, BinRead, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone)]
 pub struct BoundingBox {
     pub h: i16,
     pub y: i16,
@@ -382,6 +612,7 @@ pub struct BoundingBox {
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 #[binrw]
 #[br(map = Self::from_bytes, little)]
+#[bw(map = |x: &Self| x.into_bytes(), little)]
 pub struct ThingFlags {
     pub skill_levels_1_2: bool, 
     pub skill_level_3: bool,
@@ -405,6 +636,7 @@ pub struct ThingFlags {
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 #[binrw]
 #[br(map = Self::from_bytes, little)]
+#[bw(map = |x: &Self| x.into_bytes(), little)]
 pub struct LineDefFlags {
     pub blocks_player_and_monsters: bool, 
     pub blocks_monsters: bool,
@@ -415,11 +647,197 @@ pub struct LineDefFlags {
     pub blocks_sound: bool,
     pub never_automap: bool,
     pub always_automap: bool,
-    _unused9: bool,
-    _unused10: bool,
-    _unused11: bool,
-    _unused12: bool,
+    /// Hexen format only, the special can be triggered more than once
+    pub repeat_special: bool,
+    /// Hexen format only, how the special is triggered (crossed, used, shot...)
+    pub activation: B3,
     _unused13: bool,
     _unused14: bool,
     _unused15: bool, 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lump(name: &str, kind: LumpKind, data: &RawData) -> Lump {
+        let size = data.len() as i32;
+        Lump { offset: 0, size, name: name.to_string(), count: Lump::lump_count(&kind, size), kind, index: 0 }
+    }
+
+    /// A classified directory of the given lumps, stored one after the other
+    fn classified(entries: &[(&str, &[u8])]) -> Vec<(String, LumpKind, usize)> {
+        let mut raw_data = RawData::new();
+        let mut lumps: Lumps = entries.iter().enumerate().map(|(index, (name, data))| {
+            let offset = raw_data.len() as i32;
+            raw_data.extend(*data);
+            Lump { offset, size: data.len() as i32, name: name.to_string(), kind: LumpKind::Data, count: 0, index }
+        }).collect();
+        LumpKind::classify(&mut lumps, &raw_data);
+        lumps.into_iter().map(|lump| (lump.name, lump.kind, lump.count)).collect()
+    }
+
+    #[test]
+    fn lumps_are_classified_by_name_then_by_data() {
+        let picture = [1, 0, 1, 0, 0, 0, 0, 0, 12, 0, 0, 0, 0xFF];
+        let kinds: Vec<LumpKind> = classified(&[
+            ("PLAYPAL", &[0; 768]),
+            ("colormap", &[0; 256]),
+            ("ENDOOM", &[0; 4000]),
+            ("DEMO1", &[109]),
+            ("DEMOS", &[109]),
+            ("D_E1M1", b"MUS\x1A"),
+            ("H_MUSIC", b"MThd"),
+            ("DSPISTOL", &[3, 0, 0x11, 0x2B, 1, 0, 0, 0, 128]),
+            ("DPPISTOL", &[0, 0, 1, 0, 64]),
+            ("GSPISTOL", &[3, 0, 0x11, 0x2B, 1, 0, 0, 0, 128]),
+            ("DSSHORT", &[3, 0, 0x11, 0x2B, 9, 0, 0, 0, 128]),
+            ("TITLEPIC", &picture),
+            ("MAPINFO", b"map MAP01"),
+            ("MY_START", &[]),
+            ("MY_END", &[]),
+            ("README", b"hello"),
+        ]).into_iter().map(|(_, kind, _)| kind).collect();
+        assert_eq!(kinds, [
+            LumpKind::Pallet, LumpKind::ColourMap, LumpKind::AnsiText, LumpKind::Demo, LumpKind::Data,
+            LumpKind::Music, LumpKind::Music, LumpKind::SoundDoomFormat, LumpKind::SoundSpeaker,
+            LumpKind::SoundDoomFormat, LumpKind::Data, LumpKind::Graphics, LumpKind::MapInfo,
+            LumpKind::StartMarker, LumpKind::EndMarker, LumpKind::Data,
+        ]);
+    }
+
+    #[test]
+    fn lumps_in_maps_and_namespaces_are_classified_by_where_they_are() {
+        let picture = [1, 0, 1, 0, 0, 0, 0, 0, 12, 0, 0, 0, 0xFF];
+        let lumps = classified(&[
+            ("E1M1", &[]),
+            ("THINGS", &[0; 20]),
+            ("LINEDEFS", &[0; 14]),
+            ("VERTEXES", &[0; 13]),
+            ("GL_E1M1", &[]),
+            ("GL_VERT", &[0; 8]),
+            ("PLAYPAL", &[0; 768]),
+            ("MAP01", &[]),
+            ("TEXTMAP", b"namespace = \"zdoom\";"),
+            ("PLAYPAL", &[0; 768]),
+            ("ENDMAP", &[]),
+            ("S_START", &[]),
+            ("TROOA1", &[0; 4]),
+            ("S_END", &[]),
+            ("F_START", &[]),
+            ("F1_START", &[]),
+            ("FLOOR0_1", &[0; 4096]),
+            ("F1_END", &[]),
+            ("PLAYPAL", &[0; 768]),
+            ("F_END", &[]),
+            ("TX_START", &[]),
+            ("WALL", &picture),
+            ("TX_END", &[]),
+            ("TITLEPIC", &picture),
+        ]);
+        let kinds: Vec<(&str, LumpKind, usize)> = lumps.iter().map(|(name, kind, count)| (name.as_str(), *kind, *count)).collect();
+        assert_eq!(kinds, [
+            ("E1M1", LumpKind::MapMarker, 0),
+            ("THINGS", LumpKind::Things, 2),
+            ("LINEDEFS", LumpKind::LineDefs, 1),
+            ("VERTEXES", LumpKind::Vertexs, 3),
+            ("GL_E1M1", LumpKind::GlMarker, 0),
+            ("GL_VERT", LumpKind::GlVertexs, 8),
+            ("PLAYPAL", LumpKind::Pallet, 768),
+            ("MAP01", LumpKind::MapMarker, 0),
+            ("TEXTMAP", LumpKind::TextMap, 20),
+            // Anything between TEXTMAP and ENDMAP belongs to the map
+            ("PLAYPAL", LumpKind::Data, 768),
+            ("ENDMAP", LumpKind::EndMap, 0),
+            ("S_START", LumpKind::StartMarker, 0),
+            ("TROOA1", LumpKind::Sprite, 4),
+            ("S_END", LumpKind::EndMarker, 0),
+            ("F_START", LumpKind::StartMarker, 0),
+            ("F1_START", LumpKind::StartMarker, 0),
+            ("FLOOR0_1", LumpKind::Flat, 4096),
+            ("F1_END", LumpKind::EndMarker, 0),
+            ("PLAYPAL", LumpKind::Flat, 768),
+            ("F_END", LumpKind::EndMarker, 0),
+            ("TX_START", LumpKind::StartMarker, 0),
+            ("WALL", LumpKind::Data, 13),
+            ("TX_END", LumpKind::EndMarker, 0),
+            ("TITLEPIC", LumpKind::Graphics, 13),
+        ]);
+    }
+
+    #[test]
+    fn records_past_the_last_id_are_errors() {
+        let data = vec![0; 4 * (u16::MAX as usize + 1)];
+        let vertexes: Vec<Vertex> = lump("VERTEXES", LumpKind::Vertexs, &data).deserialize(&data).unwrap();
+        assert_eq!(vertexes.last().unwrap().id, u16::MAX as u32);
+
+        let data = vec![0; 4 * (u16::MAX as usize + 2)];
+        let result: Result<Vec<Vertex>, _> = lump("VERTEXES", LumpKind::Vertexs, &data).deserialize(&data);
+        assert!(matches!(result, Err(Error::Deserialize { reason, .. }) if reason.contains("record 65536")));
+    }
+
+    #[test]
+    fn hexen_things_have_a_tid_height_and_special() {
+        let data = [
+            5, 0, 96, 0, 0xA0, 0xFF, 16, 0, 180, 0, 0xBC, 0x0B, 0xE7, 0x00,
+            12, 1, 2, 3, 4, 5,
+        ];
+        let thing: Thing = Thing::read_le_args(&mut Cursor::new(&data), (3, MapFormat::Hexen)).unwrap();
+        assert_eq!((thing.tid, thing.x, thing.y, thing.z), (5, 96, -96, 16));
+        assert_eq!((thing.angle_facing, thing.doomed_thing_type), (180, 3004));
+        assert_eq!(thing.flags.into_bytes(), [0xE7, 0x00]);
+        assert_eq!((thing.special, thing.args, thing.id), (12, [1, 2, 3, 4, 5], 3));
+
+        let mut written = Cursor::new(Vec::new());
+        thing.write_le_args(&mut written, (MapFormat::Hexen,)).unwrap();
+        assert_eq!(written.into_inner(), data);
+
+        // Doom things are the same fields without the Hexen ones
+        let thing: Thing = Thing::read_le_args(&mut Cursor::new(&data[2..]), (0, MapFormat::Doom)).unwrap();
+        assert_eq!((thing.tid, thing.x, thing.y, thing.z, thing.angle_facing), (0, 96, -96, 0, 16));
+        assert_eq!((thing.special, thing.args), (0, [0; 5]));
+    }
+
+    #[test]
+    fn hexen_linedefs_have_args_instead_of_a_tag() {
+        let data = [0, 0, 1, 0, 0x01, 0x06, 80, 1, 2, 3, 4, 5, 2, 0, 0xFF, 0xFF];
+        let line_def: LineDef = LineDef::read_le_args(&mut Cursor::new(&data), (0, MapFormat::Hexen)).unwrap();
+        assert_eq!((line_def.start_vertex_id, line_def.end_vertex_id), (0, 1));
+        assert_eq!(line_def.flags.into_bytes(), [0x01, 0x06]);
+        assert_eq!((line_def.special_type, line_def.tag, line_def.args), (80, 0, [1, 2, 3, 4, 5]));
+        assert_eq!((line_def.front, line_def.back), (2, -1));
+
+        let mut written = Cursor::new(Vec::new());
+        line_def.write_le_args(&mut written, (MapFormat::Hexen,)).unwrap();
+        assert_eq!(written.into_inner(), data);
+
+        // In Doom format the same bytes hold a 16 bit special and a tag
+        let line_def: LineDef = LineDef::read_le_args(&mut Cursor::new(&data), (0, MapFormat::Doom)).unwrap();
+        assert_eq!((line_def.special_type, line_def.tag, line_def.front), (0x150, 0x302, 0x504));
+    }
+
+    #[test]
+    fn hexen_specials_must_fit_in_a_byte() {
+        let data = vec![0; 16];
+        let mut line_def: LineDef = LineDef::read_le_args(&mut Cursor::new(&data), (0, MapFormat::Hexen)).unwrap();
+        line_def.special_type = 255;
+        assert!(line_def.write_le_args(&mut Cursor::new(Vec::new()), (MapFormat::Hexen,)).is_ok());
+        line_def.special_type = 256;
+        assert!(line_def.write_le_args(&mut Cursor::new(Vec::new()), (MapFormat::Hexen,)).is_err());
+        assert!(line_def.write_le_args(&mut Cursor::new(Vec::new()), (MapFormat::Doom,)).is_ok());
+    }
+
+    #[test]
+    fn hexen_records_are_sized_for_their_format() {
+        // Two Hexen things, the second cut short
+        let data = vec![0; 20 + 15];
+        let things_lump = lump("THINGS", LumpKind::Things, &data);
+        let things: Vec<Thing> = things_lump.deserialize_with(&data, MapFormat::Hexen.thing_size(), |id| (id, MapFormat::Hexen)).unwrap();
+        assert_eq!(things.len(), 1);
+
+        // Three Doom linedefs' worth of bytes is only two of Hexen's
+        let data = vec![0; 14 * 3];
+        let line_defs_lump = lump("LINEDEFS", LumpKind::LineDefs, &data);
+        let line_defs: Vec<LineDef> = line_defs_lump.deserialize_with(&data, MapFormat::Hexen.line_def_size(), |id| (id, MapFormat::Hexen)).unwrap();
+        assert_eq!(line_defs.len(), 2);
+    }
+}
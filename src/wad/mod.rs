@@ -1,28 +1,73 @@
 #![allow(unused_imports)]
 mod lumps;
+mod fixed;
+mod angle;
 mod structure;
+mod namespace;
+mod writer;
+mod palette;
+mod picture;
+mod flat;
+mod texture;
+mod sound;
+mod music;
+mod genmidi;
+mod endoom;
+mod demo;
+mod blockmap;
+mod reject;
+mod gl_nodes;
+mod extended_nodes;
+mod node_builder;
+mod udmf;
+mod validate;
+mod resource;
 mod errors;
+#[cfg(test)]
+mod test_maps;
 
 use crate::cli;
 
 
 pub use errors::*;
+pub use fixed::*;
+pub use angle::*;
 pub use structure::*;
+pub use namespace::*;
+pub use writer::*;
+pub use palette::*;
+pub use picture::*;
+pub use flat::*;
+pub use texture::*;
+pub use sound::*;
+pub use music::*;
+pub use genmidi::*;
+pub use endoom::*;
+pub use demo::*;
+pub use blockmap::*;
+pub use reject::*;
+pub use gl_nodes::*;
+pub use extended_nodes::*;
+pub use node_builder::*;
+pub use udmf::*;
+pub use validate::*;
+pub use resource::*;
 pub use lumps::{
     Lump, 
+    LumpKind,
     ThingFlags,
     LineDefFlags,
+    MapFormat,
     Thing,
     LineDef,
     SideDef,
     Vertex,
     Segment,
+    SegDirection,
     SubSector,
     Node,
     BoundingBox,
     Sector,
-    Reject,
-    BlockMap,
 };
 
 use std::collections::HashMap;
@@ -58,38 +103,79 @@ pub struct Wad {
     pub raw_data: RawData,
 }
 
+impl Wad {
+    /// The raw bytes of the lump at `index` in this wad's directory
+    pub fn lump_data(&self, index: usize) -> Result<&[u8], Error> {
+        let lump = self.meta.lumps.get(index)
+            .ok_or_else(|| Error::Reader(format!("'{}' has no lump {index}", self.path.display())))?;
+        Ok(lump.data(&self.raw_data)?)
+    }
+}
+
+/// A lump found by name across the load order, along with the wad it came from
 #[derive(Debug)]
+pub struct FoundLump<'r> {
+    pub wad_name: &'r str,
+    pub wad: &'r Wad,
+    pub lump: &'r Lump,
+}
+
+impl<'r> FoundLump<'r> {
+    pub fn data(&self) -> Result<&'r [u8], Error> {
+        Ok(self.lump.data(&self.wad.raw_data)?)
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Reader {
     pub wads: Wads, 
+    /// Wad names in the order they were loaded, later wads override earlier ones
+    pub load_order: Vec<String>,
+    pub namespace: Namespace,
 }
 
 impl Reader {
     pub fn new(args: &cli::Args) -> CliResult<Self> {
-        let wads: Wads = 
-            args.wad_paths.iter().try_fold(Wads::new(), |mut wads, path| {
-                let name = path.file_stem()
-                                        .ok_or_else(|| Error::FilePath(path.clone()))?
-                                        .to_str().unwrap()
-                                        .to_string();
-
-                let mut data = fs::read(path)
-                    .or_else(|err| Err(Errors::from(Error::FileOpen(err.to_string()))))?;
-                
-                wads.insert(
-                    name,
-                    Wad {
-                        path: path.clone(),
-                        meta: WadMeta::new(&data),
-                        raw_data: data,
-                        
-                    }
-                );
-                Ok::<Wads, Errors>(wads)
-            })?;
-        Ok(Self {
-            wads,
-        })
+        let mut reader = Self::default();
+        for path in args.wad_paths.iter() {
+            let stem = path.file_stem()
+                                    .ok_or_else(|| Error::FilePath(path.clone()))?
+                                    .to_str()
+                                    .ok_or_else(|| Error::FilePath(path.clone()))?;
+
+            reader.add_wad(reader.unique_wad_name(stem), Wad::from_path(path)?);
+        }
+        Ok(reader)
+
+    }
 
+    /// Files with the same stem (say `a/doom.wad` and `b/doom.pk3`) are both kept, the later
+    /// ones getting their position in the load order appended
+    fn unique_wad_name(&self, stem: &str) -> String {
+        if !self.wads.contains_key(stem) {
+            return stem.to_string();
+        }
+        (self.load_order.len() + 1..)
+            .map(|n| format!("{stem}#{n}"))
+            .find(|name| !self.wads.contains_key(name))
+            .unwrap()
+    }
+
+    /// Add a wad to the end of the load order, its lumps override any already loaded.
+    /// Replacing a wad already loaded under the same name rebuilds the namespace, as the
+    /// lump indexes it holds for the old wad no longer apply
+    pub fn add_wad(&mut self, name: String, wad: Wad) {
+        let replaced = self.wads.insert(name.clone(), wad).is_some();
+        self.load_order.retain(|n| n != &name);
+        self.load_order.push(name.clone());
+        if replaced {
+            self.namespace = Namespace::default();
+            for wad_name in self.load_order.iter() {
+                self.namespace.add_wad(wad_name, &self.wads[wad_name].meta.lumps);
+            }
+        } else {
+            self.namespace.add_wad(&name, &self.wads[&name].meta.lumps);
+        }
     }
 
     /// Return a list of all MapMarker lumps within the given WAD directory
@@ -104,10 +190,195 @@ impl Reader {
     /// Return a Map struct for a given WAD & map_name
     pub fn get_map<'a, 'b, 'c>(&'a self, wad_name: &'b str, map_name: &'b str) -> CliResult<'c, Map>  {
         let wad = &self.wads.get(wad_name).ok_or_else(|| Error::Reader(format!("'{wad_name}' not found")))?;
-        let (i, _) = wad.meta.lumps.iter().enumerate().find(|(_, lump)| lump.name.starts_with(map_name))
-        .ok_or_else(|| Error::Reader(format!("'{map_name}' not found")))?;
-        Ok( Map::new(&wad.meta.lumps, &wad.raw_data, i))
+        let lumps = &wad.meta.lumps;
+        let i = (0..lumps.len())
+            .find(|i| lumps[*i].name.eq_ignore_ascii_case(map_name) && Map::is_marker(lumps, *i))
+            .ok_or_else(|| Error::Reader(format!("'{map_name}' not found")))?;
+        Ok( Map::new(&wad.meta.lumps, &wad.raw_data, i)?)
+    }
+
+    /// Names of every map across the load order
+    pub fn map_list(&self) -> &Vec<String> {
+        &self.namespace.map_order
+    }
+
+    /// Return the Map with the given name from the last wad in the load order that contains it,
+    /// along with the name of that wad
+    pub fn find_map<'a, 'b, 'c>(&'a self, map_name: &'b str) -> CliResult<'c, (Map, &'a str)> {
+        let found = self.resolve(&self.namespace.maps, map_name)
+            .ok_or_else(|| Error::Reader(format!("map '{map_name}' not found in any wad")))?;
+        Ok((Map::new(&found.wad.meta.lumps, &found.wad.raw_data, found.lump.index)?, found.wad_name))
+    }
+
+    /// Decode several maps at once, each on its own thread, results are in the same order as `map_names`
+    pub fn find_maps<'a>(&'a self, map_names: &[&str]) -> Vec<CliResult<'a, (Map, &'a str)>> {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = map_names.iter()
+                .map(|map_name| scope.spawn(move || self.find_map(map_name)))
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|_| Err(Error::Reader("map decoding thread panicked".to_string()).into())))
+                .collect()
+        })
+    }
+
+    /// Find a lump outside of any map or marked namespace by name across the load order
+    pub fn find_lump<'a, 'b>(&'a self, lump_name: &'b str) -> Option<FoundLump<'a>> {
+        self.resolve(&self.namespace.lumps, lump_name)
+    }
+
+    /// Find a lump in a marked namespace (sprites, flats, patches) by name across the load order
+    pub fn find_marked<'a, 'b>(&'a self, namespace: MarkedNamespace, lump_name: &'b str) -> Option<FoundLump<'a>> {
+        self.resolve(self.namespace.marked(namespace), lump_name)
+    }
+
+    /// Decode PLAYPAL and COLORMAP from the load order
+    pub fn colours<'a>(&'a self) -> CliResult<'a, Colours> {
+        let lump_data = |name: &str| self.find_lump(name)
+            .ok_or_else(|| Error::Reader(format!("'{name}' not found in any wad")))?
+            .data();
+        Ok(Colours::new(lump_data("PLAYPAL")?, lump_data("COLORMAP")?)?)
+    }
+
+    /// Decode a picture by name, looking through the sprites and patches before any other lumps
+    pub fn picture<'a>(&'a self, name: &str) -> CliResult<'a, Picture> {
+        let found = self.find_marked(MarkedNamespace::Sprites, name)
+            .or_else(|| self.find_marked(MarkedNamespace::Patches, name))
+            .or_else(|| self.find_lump(name))
+            .ok_or_else(|| Error::Reader(format!("picture '{name}' not found in any wad")))?;
+        Ok(Picture::new(found.data()?)?)
+    }
+
+    /// Decode a flat by name (as given by a sector's floor or ceiling texture)
+    pub fn flat<'a>(&'a self, name: &str) -> CliResult<'a, Flat> {
+        let found = self.find_marked(MarkedNamespace::Flats, name)
+            .ok_or_else(|| Error::Reader(format!("flat '{name}' not found in any wad")))?;
+        Ok(Flat::new(found.data()?)?)
+    }
+
+    /// Parse PNAMES, TEXTURE1 and (if present) TEXTURE2 from the load order
+    pub fn textures<'a>(&'a self) -> CliResult<'a, Textures> {
+        let pnames = self.find_lump("PNAMES")
+            .ok_or_else(|| Error::Reader("'PNAMES' not found in any wad".to_string()))?;
+        let mut textures = Textures::new(pnames.data()?)?;
+        for lump_name in ["TEXTURE1", "TEXTURE2"] {
+            if let Some(found) = self.find_lump(lump_name) {
+                textures.add_texture_lump(lump_name, found.data()?)?;
+            }
+        }
+        Ok(textures)
+    }
+
+    /// Build a wall texture (as named by a sidedef) from its patches
+    pub fn texture<'a>(&'a self, textures: &Textures, name: &str) -> CliResult<'a, CompositeTexture> {
+        let definition = textures.definition(name)
+            .ok_or_else(|| Error::Reader(format!("texture '{name}' not defined in TEXTURE1 or TEXTURE2")))?;
+        Ok(textures.compose(definition, |patch_name| {
+            let found = self.find_marked(MarkedNamespace::Patches, patch_name)
+                .or_else(|| self.find_lump(patch_name))
+                .ok_or_else(|| Error::Reader(format!("patch '{patch_name}' not found in any wad")))?;
+            Picture::new(found.data()?)
+        }))
+    }
+
+    /// Decode a DMX digital sound lump (DS*) by name
+    pub fn sound<'a>(&'a self, name: &str) -> CliResult<'a, Sound> {
+        let found = self.find_lump(name)
+            .ok_or_else(|| Error::Reader(format!("sound '{name}' not found in any wad")))?;
+        Ok(Sound::new(found.data()?)?)
+    }
+
+    /// Decode a music lump (D_*) by name
+    pub fn music<'a>(&'a self, name: &str) -> CliResult<'a, Music> {
+        let found = self.find_lump(name)
+            .ok_or_else(|| Error::Reader(format!("music '{name}' not found in any wad")))?;
+        Ok(Music::new(found.data()?)?)
+    }
+
+    /// Parse the GENMIDI OPL2 instrument bank
+    pub fn gen_midi<'a>(&'a self) -> CliResult<'a, GenMidi> {
+        let found = self.find_lump("GENMIDI")
+            .ok_or_else(|| Error::Reader("'GENMIDI' not found in any wad".to_string()))?;
+        Ok(GenMidi::new(found.data()?)?)
+    }
+
+    /// Decode the ENDOOM text screen
+    pub fn end_doom<'a>(&'a self) -> CliResult<'a, EndDoom> {
+        let found = self.find_lump("ENDOOM")
+            .ok_or_else(|| Error::Reader("'ENDOOM' not found in any wad".to_string()))?;
+        Ok(EndDoom::new(found.data()?)?)
+    }
+
+    /// Decode a demo lump (DEMO1-3) by name
+    pub fn demo<'a>(&'a self, name: &str) -> CliResult<'a, Demo> {
+        let found = self.find_lump(name)
+            .ok_or_else(|| Error::Reader(format!("demo '{name}' not found in any wad")))?;
+        Ok(Demo::new(found.data()?)?)
+    }
+
+    /// Validate every wad in load order, returning the problems found in each
+    pub fn validate(&self) -> Vec<(&str, Problems)> {
+        self.load_order.iter()
+            .filter_map(|name| self.wads.get_key_value(name))
+            .map(|(name, wad)| (name.as_str(), wad.validate()))
+            .collect()
+    }
+
+    fn resolve<'a>(&'a self, lump_refs: &'a LumpRefs, lump_name: &str) -> Option<FoundLump<'a>> {
+        // names taken from map data still carry their NUL padding
+        let lump_ref = lump_refs.get(&lump_name.trim_end_matches('\0').to_ascii_uppercase())?;
+        let (wad_name, wad) = self.wads.get_key_value(&lump_ref.wad)?;
+        Some(FoundLump {
+            wad_name,
+            wad,
+            lump: wad.meta.lumps.get(lump_ref.index)?,
+        })
     }
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_maps::*;
+
+    #[test]
+    fn replacing_a_wad_rebuilds_the_namespace() {
+        let mut reader = Reader::default();
+        reader.add_wad("doom".to_string(), wad(&[("PLAYPAL", b"palette".to_vec()), ("ENDOOM", b"endoom".to_vec())]));
+        reader.add_wad("doom".to_string(), wad(&[("FOO", b"foo".to_vec())]));
+
+        assert_eq!(reader.load_order, vec!["doom".to_string()]);
+        assert!(reader.find_lump("PLAYPAL").is_none());
+        assert!(reader.find_lump("ENDOOM").is_none());
+        assert_eq!(reader.find_lump("FOO").unwrap().data().unwrap(), b"foo");
+    }
+
+    #[test]
+    fn wads_with_the_same_stem_are_both_loaded() {
+        let directory = std::env::temp_dir().join(format!("doom_engine_reader_{}", std::process::id()));
+        fs::create_dir_all(directory.join("a")).unwrap();
+        fs::create_dir_all(directory.join("b")).unwrap();
+        fs::write(directory.join("a/doom.wad"), wad_data(&[("PLAYPAL", b"palette".to_vec()), ("ENDOOM", b"endoom".to_vec())])).unwrap();
+        fs::write(directory.join("b/doom.wad"), wad_data(&[("FOO", b"foo".to_vec()), ("ENDOOM", b"override".to_vec())])).unwrap();
+
+        let args = cli::Args {
+            wad_paths: vec![directory.join("a/doom.wad"), directory.join("b/doom.wad")],
+            map_name: None,
+            list_maps: false,
+            validate: false,
+            endoom: false,
+            screen_width: 320,
+            screen_height: 200,
+        };
+        let reader = Reader::new(&args).map_err(|err| err.to_string()).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(reader.load_order, vec!["doom".to_string(), "doom#2".to_string()]);
+        let playpal = reader.find_lump("PLAYPAL").unwrap();
+        assert_eq!((playpal.wad_name, playpal.data().unwrap()), ("doom", &b"palette"[..]));
+        let endoom = reader.find_lump("ENDOOM").unwrap();
+        assert_eq!((endoom.wad_name, endoom.data().unwrap()), ("doom#2", &b"override"[..]));
+        assert_eq!(reader.find_lump("FOO").unwrap().data().unwrap(), b"foo");
+    }
+}
@@ -0,0 +1,324 @@
+use super::*;
+
+#[derive(Debug, BinRead, PartialEq, Eq, Clone)]
+#[br(little, magic = b"MUS\x1A")]
+pub struct MusHeader {
+    pub score_length: u16,
+    pub score_start: u16,
+    pub primary_channels: u16,
+    pub secondary_channels: u16,
+    pub instrument_count: u16,
+    pub reserved: u16,
+    #[br(parse_with = binrw::helpers::count(instrument_count as usize))]
+    pub instruments: Vec<u16>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MusEvent {
+    ReleaseNote { channel: u8, note: u8 },
+    /// Without a volume the channel's last volume is used
+    PlayNote { channel: u8, note: u8, volume: Option<u8> },
+    /// 0 to 255, 128 is no bend
+    PitchBend { channel: u8, bend: u8 },
+    System { channel: u8, controller: u8 },
+    /// Controller 0 is an instrument (program) change
+    Controller { channel: u8, controller: u8, value: u8 },
+    MeasureEnd,
+    ScoreEnd,
+}
+
+/// An event along with the number of ticks (at 140Hz) to wait before the next event
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MusScoreEvent {
+    pub event: MusEvent,
+    pub delay: u32,
+}
+
+/// A decoded MUS (D_*) music lump
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Mus {
+    pub header: MusHeader,
+    pub events: Vec<MusScoreEvent>,
+}
+
+impl Mus {
+    pub const PERCUSSION_CHANNEL: u8 = 15;
+    pub const MIDI_PERCUSSION_CHANNEL: u8 = 9;
+    /// MUS ticks at 140Hz, at the default MIDI tempo of 500000us a quarter note that's 70 ticks a quarter
+    pub const MIDI_TICKS_PER_QUARTER: u16 = 70;
+    const MIDI_TEMPO: u32 = 500_000;
+
+    /// MUS system events 10 to 14 and controllers 1 to 9 as MIDI controllers
+    const SYSTEM_TO_MIDI: [u8; 5] = [120, 123, 126, 127, 121];
+    const CONTROLLER_TO_MIDI: [u8; 10] = [0, 0, 1, 7, 10, 11, 91, 93, 64, 67];
+
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let header = MusHeader::read(&mut Cursor::new(data))
+            .map_err(|err| Self::error(err.root_cause().to_string()))?;
+        let start = header.score_start as usize;
+        let score = data.get(start..)
+            .filter(|score| !score.is_empty())
+            .ok_or_else(|| Self::error(format!("score starts at {start} past the end of the lump")))?;
+
+        let mut bytes = score.iter().copied().enumerate();
+        let mut next = |what: &str| bytes.next()
+            .map(|(_, byte)| byte)
+            .ok_or_else(|| Self::error(format!("score ends in the middle of {what}")));
+
+        let mut events = Vec::new();
+        loop {
+            let descriptor = next("an event")?;
+            let channel = descriptor & 0x0F;
+            let event = match (descriptor >> 4) & 0x07 {
+                0 => MusEvent::ReleaseNote { channel, note: next("a release note")? & 0x7F },
+                1 => {
+                    let note = next("a play note")?;
+                    let volume = if note & 0x80 != 0 { Some(next("a play note")? & 0x7F) } else { None };
+                    MusEvent::PlayNote { channel, note: note & 0x7F, volume }
+                },
+                2 => MusEvent::PitchBend { channel, bend: next("a pitch bend")? },
+                3 => MusEvent::System { channel, controller: next("a system event")? & 0x7F },
+                4 => MusEvent::Controller {
+                    channel,
+                    controller: next("a controller change")? & 0x7F,
+                    value: next("a controller change")?,
+                },
+                5 => MusEvent::MeasureEnd,
+                6 => MusEvent::ScoreEnd,
+                kind => return Err(Self::error(format!("unknown event type {kind}"))),
+            };
+
+            let mut delay = 0;
+            if descriptor & 0x80 != 0 {
+                loop {
+                    let byte = next("a delay")?;
+                    delay = (delay << 7) | (byte & 0x7F) as u32;
+                    if byte & 0x80 == 0 { break }
+                }
+            }
+            events.push(MusScoreEvent { event, delay });
+            if event == MusEvent::ScoreEnd { break }
+        }
+
+        Ok(Self { header, events })
+    }
+
+    fn error(reason: String) -> Error {
+        Error::Decoding("MUS".to_string(), reason)
+    }
+
+    /// Convert to a Type-0 Standard MIDI File, MUS channel 15 becomes the MIDI percussion
+    /// channel and the others are given MIDI channels in the order they're first used
+    pub fn to_midi(&self) -> Result<RawData, Error> {
+        let mut track = RawData::new();
+        let mut channels: [Option<u8>; 16] = [None; 16];
+        let mut volumes = [127u8; 16];
+        let mut next_channel = 0u8;
+        let mut delta = 0u32;
+
+        // Set tempo
+        write_variable_length(&mut track, 0);
+        track.extend([0xFF, 0x51, 0x03]);
+        track.extend(&Self::MIDI_TEMPO.to_be_bytes()[1..]);
+
+        for MusScoreEvent { event, delay } in self.events.iter() {
+            let mut midi_channel = |channel: u8| -> Result<u8, Error> {
+                let channel = channel as usize;
+                if channel as u8 == Self::PERCUSSION_CHANNEL {
+                    return Ok(Self::MIDI_PERCUSSION_CHANNEL);
+                }
+                if let Some(midi) = channels[channel] {
+                    return Ok(midi);
+                }
+                if next_channel == Self::MIDI_PERCUSSION_CHANNEL { next_channel += 1 }
+                if next_channel > 15 {
+                    return Err(Self::error("more channels are used than MIDI has".to_string()));
+                }
+                channels[channel] = Some(next_channel);
+                next_channel += 1;
+                Ok(channels[channel].unwrap())
+            };
+
+            let message: Option<Vec<u8>> = match *event {
+                MusEvent::ReleaseNote { channel, note } => Some(vec![0x80 | midi_channel(channel)?, note, 0x40]),
+                MusEvent::PlayNote { channel, note, volume } => {
+                    if let Some(volume) = volume {
+                        volumes[channel as usize] = volume;
+                    }
+                    Some(vec![0x90 | midi_channel(channel)?, note, volumes[channel as usize]])
+                },
+                MusEvent::PitchBend { channel, bend } => {
+                    let bend = bend as u16 * 64;
+                    Some(vec![0xE0 | midi_channel(channel)?, (bend & 0x7F) as u8, (bend >> 7) as u8])
+                },
+                MusEvent::System { channel, controller } => Self::SYSTEM_TO_MIDI
+                    .get((controller as usize).wrapping_sub(10))
+                    .map(|midi| Ok::<_, Error>(vec![0xB0 | midi_channel(channel)?, *midi, 0]))
+                    .transpose()?,
+                MusEvent::Controller { channel, controller: 0, value } =>
+                    Some(vec![0xC0 | midi_channel(channel)?, value.min(127)]),
+                MusEvent::Controller { channel, controller, value } => Self::CONTROLLER_TO_MIDI
+                    .get(controller as usize)
+                    .map(|midi| Ok::<_, Error>(vec![0xB0 | midi_channel(channel)?, *midi, value.min(127)]))
+                    .transpose()?,
+                MusEvent::MeasureEnd | MusEvent::ScoreEnd => None,
+            };
+
+            if let Some(message) = message {
+                write_variable_length(&mut track, delta);
+                track.extend(message);
+                delta = 0;
+            }
+            delta += delay;
+        }
+
+        // End of track
+        write_variable_length(&mut track, delta);
+        track.extend([0xFF, 0x2F, 0x00]);
+
+        let mut midi = RawData::with_capacity(22 + track.len());
+        midi.extend(b"MThd");
+        midi.extend(6u32.to_be_bytes());
+        midi.extend(0u16.to_be_bytes()); // format 0
+        midi.extend(1u16.to_be_bytes()); // one track
+        midi.extend(Self::MIDI_TICKS_PER_QUARTER.to_be_bytes());
+        midi.extend(b"MTrk");
+        midi.extend((track.len() as u32).to_be_bytes());
+        midi.extend(track);
+        Ok(midi)
+    }
+}
+
+fn write_variable_length(out: &mut RawData, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+/// A D_ music lump, most are MUS but PWADs can store standard MIDI files directly
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Music {
+    Mus(Mus),
+    Midi(RawData),
+}
+
+impl Music {
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        if data.starts_with(b"MThd") {
+            Ok(Self::Midi(data.to_vec()))
+        } else {
+            Ok(Self::Mus(Mus::new(data)?))
+        }
+    }
+
+    pub fn to_midi(&self) -> Result<RawData, Error> {
+        match self {
+            Self::Mus(mus) => mus.to_midi(),
+            Self::Midi(midi) => Ok(midi.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A MUS lump with no instruments, so the score starts straight after the header
+    fn mus_data(score: &[u8]) -> RawData {
+        let mut data = b"MUS\x1A".to_vec();
+        for field in [score.len() as u16, 16, 1, 0, 0, 0] {
+            data.extend(field.to_le_bytes());
+        }
+        data.extend(score);
+        data
+    }
+
+    fn midi_track(midi: &[u8]) -> &[u8] {
+        assert_eq!(&midi[..4], b"MThd");
+        assert_eq!(&midi[12..14], Mus::MIDI_TICKS_PER_QUARTER.to_be_bytes());
+        assert_eq!(&midi[14..18], b"MTrk");
+        assert_eq!(u32::from_be_bytes(midi[18..22].try_into().unwrap()) as usize, midi.len() - 22);
+        &midi[22..]
+    }
+
+    #[test]
+    fn every_event_type_is_decoded() {
+        let mus = Mus::new(&mus_data(&[
+            0x91, 0x80 | 60, 100, 0x81, 0x00, // play with volume, delay of 128
+            0x01, 60,                         // release
+            0x1F, 35,                         // play without volume on percussion
+            0x22, 200,                        // pitch bend
+            0x32, 11,                         // system
+            0x42, 3, 90,                      // controller
+            0xD0, 5,                          // measure end, delay of 5
+            0x60,                             // score end
+        ])).unwrap();
+        let events: Vec<_> = mus.events.iter().map(|event| (event.event, event.delay)).collect();
+        assert_eq!(events, [
+            (MusEvent::PlayNote { channel: 1, note: 60, volume: Some(100) }, 128),
+            (MusEvent::ReleaseNote { channel: 1, note: 60 }, 0),
+            (MusEvent::PlayNote { channel: 15, note: 35, volume: None }, 0),
+            (MusEvent::PitchBend { channel: 2, bend: 200 }, 0),
+            (MusEvent::System { channel: 2, controller: 11 }, 0),
+            (MusEvent::Controller { channel: 2, controller: 3, value: 90 }, 0),
+            (MusEvent::MeasureEnd, 5),
+            (MusEvent::ScoreEnd, 0),
+        ]);
+    }
+
+    #[test]
+    fn bad_scores_are_errors() {
+        assert!(matches!(Mus::new(b"MUS"), Err(Error::Decoding(..))));
+        assert!(matches!(Mus::new(&mus_data(&[])), Err(Error::Decoding(..))));
+        assert!(matches!(Mus::new(&mus_data(&[0x91, 0x80 | 60])), Err(Error::Decoding(..))));
+        assert!(matches!(Mus::new(&mus_data(&[0x90, 60, 0x81])), Err(Error::Decoding(..))));
+        assert!(matches!(Mus::new(&mus_data(&[0x70])), Err(Error::Decoding(..))));
+    }
+
+    #[test]
+    fn midi_deltas_carry_delays_over_events_with_no_message() {
+        let mus = Mus::new(&mus_data(&[
+            0x93, 0x80 | 60, 100, 0x81, 0x0C, // play on channel 3 then wait 140 ticks
+            0xD0, 10,                         // measure end then wait 10 more
+            0x03, 60,                         // release
+            0x1F, 35,                         // percussion keeps the last volume of its own channel
+            0xE0, 2,                          // score end, the delay lands on the end of track
+        ])).unwrap();
+        assert_eq!(midi_track(&mus.to_midi().unwrap()), [
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+            0x00, 0x90, 60, 100,
+            0x81, 0x16, 0x80, 60, 0x40,
+            0x00, 0x99, 35, 127,
+            0x02, 0xFF, 0x2F, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn midi_channels_are_given_out_in_order_around_percussion() {
+        let mut score: Vec<u8> = (0..10).flat_map(|channel| [0x10 | channel, 60]).collect();
+        score.extend([0x40, 0, 200, 0x33, 10, 0x20, 128, 0x60]);
+        let midi = Mus::new(&mus_data(&score)).unwrap().to_midi().unwrap();
+        let track = midi_track(&midi);
+
+        let statuses: Vec<u8> = track[7..].chunks(4).take(10).map(|message| message[1]).collect();
+        assert_eq!(statuses, [0x90, 0x91, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x9A]);
+        assert_eq!(&track[47..], [
+            0x00, 0xC0, 127,              // instrument changes are clamped
+            0x00, 0xB3, 120, 0,           // system 10 is all sounds off
+            0x00, 0xE0, 0x00, 0x40,       // 128 is a centred bend
+            0x00, 0xFF, 0x2F, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn midi_lumps_are_kept_as_they_are() {
+        let midi = b"MThd\x00\x00\x00\x06".to_vec();
+        assert_eq!(Music::new(&midi).unwrap(), Music::Midi(midi.clone()));
+        assert_eq!(Music::new(&midi).unwrap().to_midi().unwrap(), midi);
+        assert!(matches!(Music::new(&mus_data(&[0x60])).unwrap(), Music::Mus(_)));
+    }
+}
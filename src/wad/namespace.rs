@@ -0,0 +1,114 @@
+use super::*;
+
+/// Where a lump can be found within the load order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LumpRef {
+    /// Name of the wad (as keyed in `Reader::wads`)
+    pub wad: String,
+    /// Index of the lump within that wad's directory
+    pub index: usize,
+}
+
+pub type LumpRefs = HashMap<String, LumpRef>;
+
+/// The lump namespaces that live between a pair of start/end markers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkedNamespace {
+    Sprites,
+    Flats,
+    Patches,
+    /// ZDoom's whole image textures, usually PNGs rather than pictures
+    Textures,
+}
+
+impl MarkedNamespace {
+    pub fn from_start_marker(name: &str) -> Option<Self> {
+        match name {
+            "S_START" | "SS_START" => Some(Self::Sprites),
+            "F_START" | "FF_START" => Some(Self::Flats),
+            "P_START" | "PP_START" => Some(Self::Patches),
+            "TX_START" => Some(Self::Textures),
+            _ => None,
+        }
+    }
+
+    /// Markers nested within a namespace, like the F1_START/F1_END pairs between F_START and F_END
+    pub fn is_sub_marker(name: &str, lump: &Lump) -> bool {
+        lump.size == 0 && (name.ends_with("_START") || name.ends_with("_END"))
+    }
+
+    pub fn is_end_marker(&self, name: &str) -> bool {
+        match self {
+            Self::Sprites => matches!(name, "S_END" | "SS_END"),
+            Self::Flats => matches!(name, "F_END" | "FF_END"),
+            Self::Patches => matches!(name, "P_END" | "PP_END"),
+            Self::Textures => name == "TX_END",
+        }
+    }
+}
+
+/// The merged view of every lump in the load order, wads added later override lumps
+/// of the same name from wads added earlier, maps are replaced whole while sprites,
+/// flats and patches are merged with those that came before
+#[derive(Debug, Default)]
+pub struct Namespace {
+    pub lumps: LumpRefs,
+    pub maps: LumpRefs,
+    pub sprites: LumpRefs,
+    pub flats: LumpRefs,
+    pub patches: LumpRefs,
+    pub textures: LumpRefs,
+    /// Map names in the order they were first seen
+    pub map_order: Vec<String>,
+}
+
+impl Namespace {
+    /// Merge the lumps of a wad into the namespace, overriding any lumps already present
+    pub fn add_wad(&mut self, wad_name: &str, wad_lumps: &Lumps) {
+        let mut current: Option<MarkedNamespace> = None;
+        let mut i = 0;
+        while i < wad_lumps.len() {
+            let name = wad_lumps[i].name.to_ascii_uppercase();
+            let lump_ref = LumpRef { wad: wad_name.to_string(), index: i };
+            i += 1;
+
+            if let Some(namespace) = current {
+                if namespace.is_end_marker(&name) {
+                    current = None;
+                } else if MarkedNamespace::is_sub_marker(&name, &wad_lumps[lump_ref.index]) {
+                    // F1_START, F2_END etc. only group lumps within the namespace
+                } else {
+                    self.marked_mut(namespace).insert(name, lump_ref);
+                }
+            } else if let Some(namespace) = MarkedNamespace::from_start_marker(&name) {
+                current = Some(namespace);
+            } else if Map::is_marker(wad_lumps, lump_ref.index) {
+                i = Map::map_end(wad_lumps, lump_ref.index);
+                if !self.maps.contains_key(&name) {
+                    self.map_order.push(name.clone());
+                }
+                self.maps.insert(name, lump_ref);
+            } else {
+                self.lumps.insert(name, lump_ref);
+            }
+        }
+    }
+
+    fn marked_mut(&mut self, namespace: MarkedNamespace) -> &mut LumpRefs {
+        match namespace {
+            MarkedNamespace::Sprites => &mut self.sprites,
+            MarkedNamespace::Flats => &mut self.flats,
+            MarkedNamespace::Patches => &mut self.patches,
+            MarkedNamespace::Textures => &mut self.textures,
+        }
+    }
+
+    pub fn marked(&self, namespace: MarkedNamespace) -> &LumpRefs {
+        match namespace {
+            MarkedNamespace::Sprites => &self.sprites,
+            MarkedNamespace::Flats => &self.flats,
+            MarkedNamespace::Patches => &self.patches,
+            MarkedNamespace::Textures => &self.textures,
+        }
+    }
+}
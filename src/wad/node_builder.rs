@@ -0,0 +1,430 @@
+use super::*;
+
+/// A seg while the tree is being built, its vertexes index into the builder's vertexes
+#[derive(Debug, Clone, Copy)]
+struct BuildSegment {
+    start: usize,
+    end: usize,
+    line_def_id: u32,
+    direction: SegDirection,
+    /// Distance along the linedef to the start of the seg
+    offset: f64,
+}
+
+/// Which side of a partition line something lies on
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Side {
+    Front,
+    Back,
+    On,
+}
+
+/// The segs, subsectors and nodes for a map, along with its vertexes and any added where
+/// segs were split
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BuiltNodes {
+    pub vertexes: Vec<Vertex>,
+    pub segments: Vec<Segment>,
+    pub sub_sectors: Vec<SubSector>,
+    pub nodes: Vec<Node>,
+}
+
+/// Builds a BSP tree for maps that don't have one, like those fresh out of an editor.
+/// Partitions are picked from the segs themselves, preferring those that split the
+/// fewest segs and leave the two sides most evenly balanced. Only sides facing a sector
+/// get segs
+#[derive(Debug)]
+pub struct NodeBuilder {
+    vertexes: Vec<(f64, f64)>,
+    segments: Vec<Segment>,
+    sub_sectors: Vec<SubSector>,
+    nodes: Vec<Node>,
+}
+
+impl NodeBuilder {
+    /// Points closer than this to a partition line count as being on it
+    const EPSILON: f64 = 0.01;
+    /// Above this many segs only a spread of them are tried as partitions
+    const MAX_CANDIDATES: usize = 64;
+    const SPLIT_COST: usize = 8;
+    /// Floating point error could in theory keep splitting the same segs forever
+    const MAX_DEPTH: usize = 256;
+
+    pub fn build(vertexes: &[Vertex], line_defs: &[LineDef], side_defs: &[SideDef], sectors: &[Sector]) -> BuiltNodes {
+        let mut builder = Self {
+            vertexes: vertexes.iter().map(|v| (v.x as f64, v.y as f64)).collect(),
+            segments: Vec::new(),
+            sub_sectors: Vec::new(),
+            nodes: Vec::new(),
+        };
+
+        // A side without a sector has nothing to draw or collide with
+        let has_side = |side_def_id: i16| usize::try_from(side_def_id).ok()
+            .and_then(|id| side_defs.get(id))
+            .and_then(|side_def| usize::try_from(side_def.sector_this_sidedef_faces).ok())
+            .is_some_and(|sector| sector < sectors.len());
+        let mut segments = Vec::new();
+        for (id, line_def) in line_defs.iter().enumerate() {
+            let (start, end) = (line_def.start_vertex_id as usize, line_def.end_vertex_id as usize);
+            if start >= vertexes.len() || end >= vertexes.len() || start == end {
+                continue;
+            }
+            if has_side(line_def.front) {
+                segments.push(BuildSegment { start, end, line_def_id: id as u32, direction: SegDirection::SameAsLineDef, offset: 0.0 });
+            }
+            if has_side(line_def.back) {
+                segments.push(BuildSegment { start: end, end: start, line_def_id: id as u32, direction: SegDirection::OppositOfLineDef, offset: 0.0 });
+            }
+        }
+
+        if !segments.is_empty() {
+            builder.build_subtree(segments, 0);
+        }
+        BuiltNodes {
+            vertexes: builder.vertexes.iter().enumerate()
+                .map(|(id, (x, y))| Vertex { x: x.round() as i16, y: y.round() as i16, id: id as u32 })
+                .collect(),
+            segments: builder.segments,
+            sub_sectors: builder.sub_sectors,
+            nodes: builder.nodes,
+        }
+    }
+
+    /// Returns the id of the node or subsector (with `SubSector::SUB_SECTOR_IDENTIFIER` set)
+    /// built from the segs, children are added before their parents so the root is last
+    fn build_subtree(&mut self, segments: Vec<BuildSegment>, depth: usize) -> u32 {
+        let partition = (depth < Self::MAX_DEPTH).then(|| self.choose_partition(&segments)).flatten();
+        let Some(partition) = partition else {
+            return self.add_sub_sector(segments);
+        };
+        let (front, back) = self.split(&segments, &partition);
+        if front.is_empty() || back.is_empty() {
+            return self.add_sub_sector(segments);
+        }
+
+        let front_bbox = self.bounding_box(&front);
+        let back_bbox = self.bounding_box(&back);
+        let front_child_id = self.build_subtree(front, depth + 1);
+        let back_child_id = self.build_subtree(back, depth + 1);
+
+        let (x1, y1) = self.vertexes[partition.start];
+        let (x2, y2) = self.vertexes[partition.end];
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            x_partion: x1.round() as i16,
+            y_partion: y1.round() as i16,
+            dx_partion: (x2.round() - x1.round()) as i16,
+            dy_partion: (y2.round() - y1.round()) as i16,
+            front_bbox,
+            back_bbox,
+            front_child_id,
+            back_child_id,
+            id: id as u32,
+        });
+        id as u32
+    }
+
+    fn add_sub_sector(&mut self, segments: Vec<BuildSegment>) -> u32 {
+        let id = self.sub_sectors.len();
+        self.sub_sectors.push(SubSector {
+            segments_count: segments.len() as u32,
+            first_segments_id: self.segments.len() as u32,
+            id: id as u32,
+        });
+        for segment in segments {
+            let start = self.vertexes[segment.start];
+            let end = self.vertexes[segment.end];
+            self.segments.push(Segment {
+                start_vertext_id: segment.start as u32,
+                end_verext_id: segment.end as u32,
+                angle: Segment::angle_between(start, end),
+                line_def_id: Some(segment.line_def_id),
+                direction: segment.direction,
+                offset: segment.offset.round() as i16,
+                id: self.segments.len() as u32,
+            });
+        }
+        SubSector::SUB_SECTOR_IDENTIFIER | id as u32
+    }
+
+    /// The seg whose line makes the best partition, or none if the segs already form a
+    /// convex subsector
+    fn choose_partition(&self, segments: &[BuildSegment]) -> Option<BuildSegment> {
+        let step = (segments.len() / Self::MAX_CANDIDATES).max(1);
+        let mut best: Option<(usize, BuildSegment)> = None;
+
+        for candidate in segments.iter().step_by(step).filter(|candidate| self.can_partition(candidate)) {
+            let (mut front, mut back, mut splits) = (0usize, 0usize, 0usize);
+            for segment in segments.iter() {
+                match (self.side_of(candidate, segment.start), self.side_of(candidate, segment.end)) {
+                    (Side::On, Side::On) => {
+                        if self.same_direction(candidate, segment) { front += 1 } else { back += 1 }
+                    }
+                    (Side::Front | Side::On, Side::Front | Side::On) => front += 1,
+                    (Side::Back | Side::On, Side::Back | Side::On) => back += 1,
+                    _ => splits += 1,
+                }
+            }
+            if (back == 0 || front == 0) && splits == 0 {
+                continue;
+            }
+            let cost = splits * Self::SPLIT_COST + front.abs_diff(back);
+            if best.is_none_or(|(best_cost, _)| cost < best_cost) {
+                best = Some((cost, *candidate));
+            }
+        }
+
+        // Sampling can miss every partition that works, so make sure the segs really are
+        // convex by trying them all
+        if best.is_none() && step > 1 {
+            return self.choose_partition_from_all(segments);
+        }
+        best.map(|(_, partition)| partition)
+    }
+
+    fn choose_partition_from_all(&self, segments: &[BuildSegment]) -> Option<BuildSegment> {
+        segments.iter().filter(|candidate| self.can_partition(candidate)).find(|candidate| {
+            let sides = segments.iter().flat_map(|segment| [self.side_of(candidate, segment.start), self.side_of(candidate, segment.end)]);
+            let (front, back) = sides.fold((false, false), |(front, back), side| (front || side == Side::Front, back || side == Side::Back));
+            front && back
+        }).copied()
+    }
+
+    /// Nodes store their partition in whole units, so only segs that start and end on whole
+    /// units are tried as partitions and the engine sees the line the segs were divided by.
+    /// Segs too short to still have a direction when rounded can't be partitions either
+    fn can_partition(&self, candidate: &BuildSegment) -> bool {
+        let (x1, y1) = self.vertexes[candidate.start];
+        let (x2, y2) = self.vertexes[candidate.end];
+        let whole = |value: f64| (value - value.round()).abs() < Self::EPSILON;
+        whole(x1) && whole(y1) && whole(x2) && whole(y2) && (x1.round(), y1.round()) != (x2.round(), y2.round())
+    }
+
+    /// Divide the segs between the two sides of the partition, splitting those that cross it
+    fn split(&mut self, segments: &[BuildSegment], partition: &BuildSegment) -> (Vec<BuildSegment>, Vec<BuildSegment>) {
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        for segment in segments.iter() {
+            match (self.side_of(partition, segment.start), self.side_of(partition, segment.end)) {
+                (Side::On, Side::On) => {
+                    if self.same_direction(partition, segment) { front.push(*segment) } else { back.push(*segment) }
+                }
+                (Side::Front | Side::On, Side::Front | Side::On) => front.push(*segment),
+                (Side::Back | Side::On, Side::Back | Side::On) => back.push(*segment),
+                (start_side, _) => {
+                    let (first, second) = self.split_segment(partition, segment);
+                    if start_side == Side::Front {
+                        front.push(first);
+                        back.push(second);
+                    } else {
+                        back.push(first);
+                        front.push(second);
+                    }
+                }
+            }
+        }
+        (front, back)
+    }
+
+    /// Split a seg where it crosses the partition, adding a vertex there
+    fn split_segment(&mut self, partition: &BuildSegment, segment: &BuildSegment) -> (BuildSegment, BuildSegment) {
+        let start_distance = self.distance(partition, segment.start);
+        let end_distance = self.distance(partition, segment.end);
+        let t = start_distance / (start_distance - end_distance);
+
+        let (x1, y1) = self.vertexes[segment.start];
+        let (x2, y2) = self.vertexes[segment.end];
+        let split_point = (x1 + (x2 - x1) * t, y1 + (y2 - y1) * t);
+        let split_vertex = self.vertexes.len();
+        self.vertexes.push(split_point);
+
+        let first = BuildSegment { end: split_vertex, ..*segment };
+        let second = BuildSegment {
+            start: split_vertex,
+            offset: segment.offset + (split_point.0 - x1).hypot(split_point.1 - y1),
+            ..*segment
+        };
+        (first, second)
+    }
+
+    /// Signed distance of a vertex from the partition's line as the node stores it,
+    /// positive is in front
+    fn distance(&self, partition: &BuildSegment, vertex: usize) -> f64 {
+        let (x1, y1) = self.vertexes[partition.start];
+        let (x2, y2) = self.vertexes[partition.end];
+        let (x1, y1, x2, y2) = (x1.round(), y1.round(), x2.round(), y2.round());
+        let (x, y) = self.vertexes[vertex];
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        ((x - x1) * dy - (y - y1) * dx) / dx.hypot(dy)
+    }
+
+    fn side_of(&self, partition: &BuildSegment, vertex: usize) -> Side {
+        match self.distance(partition, vertex) {
+            distance if distance > Self::EPSILON => Side::Front,
+            distance if distance < -Self::EPSILON => Side::Back,
+            _ => Side::On,
+        }
+    }
+
+    fn same_direction(&self, partition: &BuildSegment, segment: &BuildSegment) -> bool {
+        let direction = |s: &BuildSegment| {
+            let (x1, y1) = self.vertexes[s.start];
+            let (x2, y2) = self.vertexes[s.end];
+            (x2 - x1, y2 - y1)
+        };
+        let (pdx, pdy) = direction(partition);
+        let (sdx, sdy) = direction(segment);
+        pdx * sdx + pdy * sdy > 0.0
+    }
+
+    fn bounding_box(&self, segments: &[BuildSegment]) -> BoundingBox {
+        let points = segments.iter().flat_map(|s| [self.vertexes[s.start], self.vertexes[s.end]]);
+        let (left, right, bottom, top) = points.fold(
+            (f64::MAX, f64::MIN, f64::MAX, f64::MIN),
+            |(left, right, bottom, top), (x, y)| (left.min(x), right.max(x), bottom.min(y), top.max(y)),
+        );
+        BoundingBox {
+            h: top.ceil() as i16,
+            y: bottom.floor() as i16,
+            w: left.floor() as i16,
+            x: right.ceil() as i16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_maps::*;
+
+    fn built(lumps: TestLumps) -> Map {
+        let lumps = with_lump(lumps, "SEGS", RawData::new());
+        let lumps = with_lump(lumps, "SSECTORS", RawData::new());
+        map(&with_lump(lumps, "NODES", RawData::new())).unwrap()
+    }
+
+    /// The square room divided in two by a two-sided line from (64, 0) to (64, 128)
+    fn divided_room() -> TestLumps {
+        let lumps = with_lump(square_room(), "VERTEXES", words(&[0, 0, 0, 128, 128, 128, 128, 0, 64, 0, 64, 128]));
+        let lumps = with_lump(lumps, "SIDEDEFS", [
+            side_def(0, "STARTAN3", 0), side_def(0, "STARTAN3", 0),
+            side_def(0, "SW1START", 0), side_def(64, "STARTAN3", 0),
+            side_def(0, "-", 0), side_def(0, "-", 0),
+        ].concat());
+        with_lump(lumps, "LINEDEFS", words(&[
+            0, 1, 1, 0, 0, 0, -1,
+            1, 2, 1, 0, 0, 1, -1,
+            2, 3, 1, 11, 0, 2, -1,
+            3, 0, 1, 0, 0, 3, -1,
+            4, 5, 4, 0, 0, 4, 5,
+        ]))
+    }
+
+    #[test]
+    fn convex_rooms_are_a_single_subsector() {
+        let map = built(square_room());
+        assert_eq!(map.nodes, []);
+        assert_eq!(map.sub_sectors.len(), 1);
+        assert_eq!(map.sub_sectors[0].to_range(), 0..4);
+        assert_eq!(map.segments.iter().map(|segment| segment.line_def_id).collect::<Vec<_>>(), [Some(0), Some(1), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn the_partition_splits_the_segs_crossing_it() {
+        let map = built(divided_room());
+        assert_eq!(map.nodes.len(), 1);
+        let node = map.nodes[0];
+        assert_eq!((node.x_partion, node.y_partion, node.dx_partion, node.dy_partion), (64, 0, 0, 128));
+        assert_eq!(node.front_child_id & SubSector::SUB_SECTOR_IDENTIFIER, SubSector::SUB_SECTOR_IDENTIFIER);
+        assert_eq!(node.back_child_id & SubSector::SUB_SECTOR_IDENTIFIER, SubSector::SUB_SECTOR_IDENTIFIER);
+
+        // The top and bottom walls are each split in two where they cross x = 64
+        assert_eq!(map.vertexes.len(), 8);
+        assert_eq!(map.sub_sectors.iter().map(|sub_sector| sub_sector.segments_count).collect::<Vec<_>>(), [4, 4]);
+        let halves: Vec<_> = map.segments.iter()
+            .filter(|segment| segment.line_def_id == Some(1))
+            .map(|segment| (map.vertexes[segment.start_vertext_id as usize], segment.offset))
+            .map(|(start, offset)| (start.x, start.y, offset))
+            .collect();
+        assert_eq!(halves.len(), 2);
+        assert!(halves.contains(&(0, 128, 0)));
+        assert!(halves.contains(&(64, 128, 64)));
+    }
+
+    #[test]
+    fn the_tree_reaches_every_seg() {
+        let map = built(divided_room());
+        let root = map.nodes.last().unwrap();
+        let mut segments: Vec<u32> = [root.front_child_id, root.back_child_id].iter()
+            .flat_map(|child| map.sub_sectors[(child & SubSector::IDENTIFIER_BITMASK) as usize].to_range())
+            .map(|id| map.segments[id].id)
+            .collect();
+        segments.sort_unstable();
+        assert_eq!(segments, (0..map.segments.len() as u32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn each_half_faces_its_own_sector() {
+        // The divided room with each half its own sector, the top and bottom walls are
+        // split where the halves meet
+        let sectors = [&square_room()[7].1[..], &square_room()[7].1[..]].concat();
+        let lumps = with_lump(divided_room(), "SECTORS", sectors);
+        let lumps = with_lump(lumps, "SIDEDEFS", [
+            side_def(0, "STARTAN3", 0), side_def(0, "STARTAN3", 0), side_def(0, "STARTAN3", 1),
+            side_def(0, "SW1START", 1), side_def(0, "STARTAN3", 1), side_def(0, "STARTAN3", 0),
+            side_def(0, "-", 1), side_def(0, "-", 0),
+        ].concat());
+        let lumps = with_lump(lumps, "LINEDEFS", words(&[
+            0, 1, 1, 0, 0, 0, -1,
+            1, 5, 1, 0, 0, 1, -1,
+            5, 2, 1, 0, 0, 2, -1,
+            2, 3, 1, 0, 0, 3, -1,
+            3, 4, 1, 0, 0, 4, -1,
+            4, 0, 1, 0, 0, 5, -1,
+            4, 5, 4, 0, 0, 6, 7,
+        ]));
+        let map = built(lumps);
+        for sub_sector in map.sub_sectors.iter() {
+            let mut sectors: Vec<_> = map.segments[sub_sector.to_range()].iter()
+                .map(|segment| {
+                    let line_def = map.line_defs[segment.line_def_id.unwrap() as usize];
+                    let side = if segment.direction == SegDirection::SameAsLineDef { line_def.front } else { line_def.back };
+                    map.side_defs[side as usize].sector_this_sidedef_faces
+                })
+                .collect();
+            sectors.dedup();
+            assert_eq!(sectors.len(), 1);
+        }
+    }
+
+    #[test]
+    fn sides_without_a_sector_have_no_segs() {
+        let lumps = with_lump(square_room(), "SIDEDEFS", [
+            side_def(0, "STARTAN3", 0), side_def(0, "STARTAN3", 0),
+            side_def(0, "SW1START", 0), side_def(64, "STARTAN3", 7),
+        ].concat());
+        let map = built(lumps);
+        assert_eq!(map.segments.iter().map(|segment| segment.line_def_id).collect::<Vec<_>>(), [Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn partitions_are_whole_and_have_a_direction() {
+        let builder = NodeBuilder {
+            vertexes: vec![(0.0, 0.0), (64.0, 0.0), (32.5, 10.0), (0.001, 0.0), (0.4, 0.3)],
+            segments: Vec::new(),
+            sub_sectors: Vec::new(),
+            nodes: Vec::new(),
+        };
+        let segment = |start, end| BuildSegment { start, end, line_def_id: 0, direction: SegDirection::SameAsLineDef, offset: 0.0 };
+        assert!(builder.can_partition(&segment(0, 1)));
+        assert!(!builder.can_partition(&segment(0, 2)));
+        assert!(!builder.can_partition(&segment(0, 3)));
+        assert!(!builder.can_partition(&segment(0, 4)));
+    }
+
+    #[test]
+    fn maps_with_subsectors_but_no_nodes_are_built() {
+        let map = map(&with_lump(square_room(), "NODES", RawData::new())).unwrap();
+        assert_eq!(map.sub_sectors.len(), 1);
+        assert_eq!(map.segments.len(), 4);
+    }
+}
@@ -0,0 +1,175 @@
+use super::*;
+
+pub const PALETTE_SIZE: usize = 256;
+
+#[derive(Debug, BinRead, BinWrite, PartialEq, Eq, Copy, Clone, Default)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub fn to_rgba(self, alpha: u8) -> [u8; 4] {
+        [self.r, self.g, self.b, alpha]
+    }
+}
+
+#[derive(Debug, BinRead, PartialEq, Eq, Clone)]
+pub struct Palette {
+    pub colours: [Rgb; PALETTE_SIZE],
+}
+
+impl Palette {
+    pub fn rgb(&self, index: u8) -> Rgb {
+        self.colours[index as usize]
+    }
+}
+
+/// The PLAYPAL lump, palette 0 is the normal palette, 1-8 are the red pain/berserk tints,
+/// 9-12 the yellow item pickup tints and 13 the green radiation suit tint
+#[derive(Debug, BinRead, PartialEq, Eq, Clone)]
+#[br(import(count: usize))]
+pub struct PlayPal {
+    #[br(parse_with = binrw::helpers::count(count))]
+    pub palettes: Vec<Palette>,
+}
+
+impl PlayPal {
+    pub const COUNT: usize = 14;
+    pub const NORMAL: usize = 0;
+    pub const RADIATION_SUIT: usize = 13;
+
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let count = data.len() / (PALETTE_SIZE * 3);
+        if count == 0 {
+            return Err(Error::Decoding("PLAYPAL".to_string(), format!("{} bytes is too short for a palette", data.len())));
+        }
+        Self::read_le_args(&mut Cursor::new(data), (count,))
+            .map_err(|err| Error::Decoding("PLAYPAL".to_string(), err.root_cause().to_string()))
+    }
+
+    pub fn palette(&self, palette: usize) -> Option<&Palette> {
+        self.palettes.get(palette)
+    }
+}
+
+/// Maps a palette index to the palette index to draw at a given light level
+#[derive(Debug, BinRead, PartialEq, Eq, Clone)]
+pub struct LightTable {
+    pub indexes: [u8; PALETTE_SIZE],
+}
+
+/// The COLORMAP lump, tables 0-31 go from full brightness to darkest, 32 is the
+/// invulnerability effect and 33 is all black
+#[derive(Debug, BinRead, PartialEq, Eq, Clone)]
+#[br(import(count: usize))]
+pub struct ColourMap {
+    #[br(parse_with = binrw::helpers::count(count))]
+    pub light_tables: Vec<LightTable>,
+}
+
+impl ColourMap {
+    pub const COUNT: usize = 34;
+    pub const LIGHT_LEVELS: usize = 32;
+    pub const INVULNERABILITY: usize = 32;
+
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let count = data.len() / PALETTE_SIZE;
+        if count == 0 {
+            return Err(Error::Decoding("COLORMAP".to_string(), format!("{} bytes is too short for a light table", data.len())));
+        }
+        Self::read_le_args(&mut Cursor::new(data), (count,))
+            .map_err(|err| Error::Decoding("COLORMAP".to_string(), err.root_cause().to_string()))
+    }
+
+    /// The light table for a sector light level (0-255), brighter sectors use lower tables
+    pub fn table_for_light_level(light_level: i16) -> usize {
+        Self::LIGHT_LEVELS - 1 - (light_level.clamp(0, 255) as usize >> 3)
+    }
+
+    /// Palette index to draw `index` with under the given light table
+    pub fn index(&self, light_table: usize, index: u8) -> Option<u8> {
+        self.light_tables.get(light_table).map(|table| table.indexes[index as usize])
+    }
+}
+
+/// PLAYPAL and COLORMAP together, everything needed to turn a palette index into a Doom colour
+#[derive(Debug, Clone)]
+pub struct Colours {
+    pub play_pal: PlayPal,
+    pub colour_map: ColourMap,
+}
+
+impl Colours {
+    pub fn new(play_pal: &[u8], colour_map: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            play_pal: PlayPal::new(play_pal)?,
+            colour_map: ColourMap::new(colour_map)?,
+        })
+    }
+
+    /// Colour of a palette index in the given palette under the given light table
+    pub fn rgb(&self, palette: usize, light_table: usize, index: u8) -> Option<Rgb> {
+        let index = self.colour_map.index(light_table, index)?;
+        Some(self.play_pal.palette(palette)?.rgb(index))
+    }
+
+    /// Colour of a palette index in the normal palette at a sector light level
+    pub fn rgb_at_light_level(&self, light_level: i16, index: u8) -> Option<Rgb> {
+        self.rgb(PlayPal::NORMAL, ColourMap::table_for_light_level(light_level), index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Palette `p` has index `i` as (i, p, 255 - i)
+    fn play_pal_data(count: usize) -> Vec<u8> {
+        (0..count).flat_map(|p| (0..PALETTE_SIZE).flat_map(move |i| [i as u8, p as u8, 255 - i as u8])).collect()
+    }
+
+    /// Table `t` maps every index to `t`, except in table 0 which leaves them as they are
+    fn colour_map_data(count: usize) -> Vec<u8> {
+        (0..count).flat_map(|t| (0..PALETTE_SIZE).map(move |i| if t == 0 { i as u8 } else { t as u8 })).collect()
+    }
+
+    #[test]
+    fn every_palette_is_read() {
+        let play_pal = PlayPal::new(&play_pal_data(PlayPal::COUNT)).unwrap();
+        assert_eq!(play_pal.palettes.len(), PlayPal::COUNT);
+        assert_eq!(play_pal.palette(PlayPal::RADIATION_SUIT).unwrap().rgb(7), Rgb { r: 7, g: 13, b: 248 });
+        assert_eq!(play_pal.palette(PlayPal::COUNT), None);
+        assert_eq!(Rgb { r: 1, g: 2, b: 3 }.to_rgba(4), [1, 2, 3, 4]);
+
+        // A partial palette at the end is ignored, less than one is an error
+        assert_eq!(PlayPal::new(&play_pal_data(2)[..PALETTE_SIZE * 3 + 10]).unwrap().palettes.len(), 1);
+        assert!(matches!(PlayPal::new(&[0; 100]), Err(Error::Decoding(..))));
+    }
+
+    #[test]
+    fn light_levels_pick_darker_tables_as_they_fall() {
+        assert_eq!(ColourMap::table_for_light_level(255), 0);
+        assert_eq!(ColourMap::table_for_light_level(160), 11);
+        assert_eq!(ColourMap::table_for_light_level(0), 31);
+        assert_eq!(ColourMap::table_for_light_level(-20), 31);
+        assert_eq!(ColourMap::table_for_light_level(1000), 0);
+
+        let colour_map = ColourMap::new(&colour_map_data(ColourMap::COUNT)).unwrap();
+        assert_eq!(colour_map.light_tables.len(), ColourMap::COUNT);
+        assert_eq!(colour_map.index(0, 200), Some(200));
+        assert_eq!(colour_map.index(ColourMap::INVULNERABILITY, 200), Some(32));
+        assert_eq!(colour_map.index(ColourMap::COUNT, 200), None);
+        assert!(matches!(ColourMap::new(&[0; 255]), Err(Error::Decoding(..))));
+    }
+
+    #[test]
+    fn colours_go_through_the_light_table_then_the_palette() {
+        let colours = Colours::new(&play_pal_data(PlayPal::COUNT), &colour_map_data(ColourMap::COUNT)).unwrap();
+        assert_eq!(colours.rgb_at_light_level(255, 9), Some(Rgb { r: 9, g: 0, b: 246 }));
+        assert_eq!(colours.rgb_at_light_level(160, 9), Some(Rgb { r: 11, g: 0, b: 244 }));
+        assert_eq!(colours.rgb(1, 31, 9), Some(Rgb { r: 31, g: 1, b: 224 }));
+        assert_eq!(colours.rgb(PlayPal::COUNT, 0, 9), None);
+    }
+}
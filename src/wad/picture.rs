@@ -0,0 +1,197 @@
+use super::*;
+
+#[derive(Debug, BinRead)]
+#[br(little)]
+struct PictureHeader {
+    width: u16,
+    height: u16,
+    left_offset: i16,
+    top_offset: i16,
+    #[br(parse_with = binrw::helpers::count(width as usize))]
+    column_offsets: Vec<u32>,
+}
+
+/// The largest width and height a picture is decoded with
+const MAX_PICTURE_SIZE: u16 = 4096;
+
+/// A decoded Doom picture (patch), pixels are palette indexes stored row by row,
+/// `None` is a transparent pixel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Picture {
+    pub width: u16,
+    pub height: u16,
+    pub left_offset: i16,
+    pub top_offset: i16,
+    pub pixels: Vec<Option<u8>>,
+}
+
+impl Picture {
+    /// Decode a picture lump, column pointers and posts that fall outside of the lump
+    /// are reported rather than followed
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        // The size is checked before anything is allocated for it
+        Self::check_size(data).map_err(Self::error)?;
+        let header = PictureHeader::read(&mut Cursor::new(data))
+            .map_err(|err| Self::error(err.root_cause().to_string()))?;
+        let (width, height) = (header.width as usize, header.height as usize);
+        let mut pixels = vec![None; width * height];
+
+        for (x, column_offset) in header.column_offsets.iter().enumerate() {
+            let mut position = *column_offset as usize;
+            let mut top_delta: Option<usize> = None;
+            loop {
+                let post_top = *data.get(position)
+                    .ok_or_else(|| Self::error(format!("column {x} runs past the end of the lump at {position}")))?;
+                if post_top == 0xFF {
+                    break;
+                }
+                // Tall patches store the top of a post relative to the previous one
+                // once the absolute value would no longer fit in a byte
+                let post_top = match top_delta {
+                    Some(previous) if post_top as usize <= previous => previous + post_top as usize,
+                    _ => post_top as usize,
+                };
+                top_delta = Some(post_top);
+
+                let length = *data.get(position + 1)
+                    .ok_or_else(|| Self::error(format!("post in column {x} at {position} is missing its length")))? as usize;
+                let post_pixels = data.get(position + 3..position + 3 + length)
+                    .ok_or_else(|| Self::error(format!("post in column {x} at {position} runs past the end of the lump")))?;
+
+                for (y, index) in (post_top..).zip(post_pixels.iter()).take_while(|(y, _)| *y < height) {
+                    pixels[y * width + x] = Some(*index);
+                }
+                // top delta, length, a padding byte either side of the pixels
+                position += length + 4;
+            }
+        }
+
+        Ok(Self {
+            width: header.width,
+            height: header.height,
+            left_offset: header.left_offset,
+            top_offset: header.top_offset,
+            pixels,
+        })
+    }
+
+    /// A quick check of whether some data could be a picture, the size is plausible and
+    /// every column starts within the data
+    pub fn is_valid_header(data: &[u8]) -> bool {
+        if Self::check_size(data).is_err() {
+            return false;
+        }
+        let Ok(header) = PictureHeader::read(&mut Cursor::new(data)) else { return false };
+        let columns_start = 8 + 4 * header.width as usize;
+        header.column_offsets.iter().all(|offset| (columns_start..data.len()).contains(&(*offset as usize)))
+    }
+
+    /// Check the width and height are plausible and the column offsets fit in the data
+    fn check_size(data: &[u8]) -> Result<(), String> {
+        let [width_low, width_high, height_low, height_high, ..] = *data else {
+            return Err(format!("the header doesn't fit in {} bytes", data.len()));
+        };
+        let (width, height) = (u16::from_le_bytes([width_low, width_high]), u16::from_le_bytes([height_low, height_high]));
+        if !(1..=MAX_PICTURE_SIZE).contains(&width) || !(1..=MAX_PICTURE_SIZE).contains(&height) {
+            return Err(format!("{width}x{height} is outside of 1x1 to {MAX_PICTURE_SIZE}x{MAX_PICTURE_SIZE}"));
+        }
+        if 8 + 4 * width as usize > data.len() {
+            return Err(format!("{width} column offsets don't fit in {} bytes", data.len()));
+        }
+        Ok(())
+    }
+
+    fn error(reason: String) -> Error {
+        Error::Decoding("picture".to_string(), reason)
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= self.width as usize || y >= self.height as usize {
+            return None;
+        }
+        self.pixels[y * self.width as usize + x]
+    }
+
+    /// Convert to RGBA bytes row by row, transparent pixels are fully transparent black
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.pixels.iter().flat_map(|pixel| match pixel {
+            Some(index) => palette.rgb(*index).to_rgba(255),
+            None => [0, 0, 0, 0],
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a picture lump from each column's posts of (top delta, pixels)
+    fn patch(height: u16, left_offset: i16, top_offset: i16, columns: &[&[(u8, &[u8])]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend((columns.len() as u16).to_le_bytes());
+        data.extend(height.to_le_bytes());
+        data.extend(left_offset.to_le_bytes());
+        data.extend(top_offset.to_le_bytes());
+        let mut posts = Vec::new();
+        for column in columns {
+            data.extend((8 + 4 * columns.len() as u32 + posts.len() as u32).to_le_bytes());
+            for (top_delta, pixels) in column.iter() {
+                posts.extend([*top_delta, pixels.len() as u8, 0]);
+                posts.extend(pixels.iter());
+                posts.push(0);
+            }
+            posts.push(0xFF);
+        }
+        data.extend(posts);
+        data
+    }
+
+    #[test]
+    fn posts_are_drawn_into_their_columns() {
+        let data = patch(4, 1, 3, &[&[(0, &[1, 2])], &[], &[(1, &[3]), (3, &[4])]]);
+        assert!(Picture::is_valid_header(&data));
+        let picture = Picture::new(&data).unwrap();
+        assert_eq!((picture.width, picture.height), (3, 4));
+        assert_eq!((picture.left_offset, picture.top_offset), (1, 3));
+        assert_eq!(picture.pixels, vec![
+            Some(1), None, None,
+            Some(2), None, Some(3),
+            None, None, None,
+            None, None, Some(4),
+        ]);
+    }
+
+    #[test]
+    fn tall_patches_continue_from_the_previous_post() {
+        // The third post's top delta is no larger than the second's so it's relative to it
+        let data = patch(300, 0, 0, &[&[(0, &[1]), (254, &[2; 10]), (30, &[3])]]);
+        let picture = Picture::new(&data).unwrap();
+        assert_eq!(picture.pixel(0, 0), Some(1));
+        assert_eq!(picture.pixel(0, 1), None);
+        assert_eq!(picture.pixel(0, 263), Some(2));
+        assert_eq!(picture.pixel(0, 264), None);
+        assert_eq!(picture.pixel(0, 284), Some(3));
+        assert_eq!(picture.pixels.iter().flatten().count(), 12);
+    }
+
+    #[test]
+    fn implausible_headers_are_rejected_before_decoding() {
+        let mut data = patch(4, 0, 0, &[&[(0, &[1])]]);
+        data[..4].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(!Picture::is_valid_header(&data));
+        assert!(matches!(Picture::new(&data), Err(Error::Decoding(_, reason)) if reason.contains("65535x65535")));
+
+        // A width that's plausible but has more column offsets than there's data for
+        data[..4].copy_from_slice(&[0x00, 0x10, 0x04, 0x00]);
+        assert!(matches!(Picture::new(&data), Err(Error::Decoding(_, reason)) if reason.contains("4096 column offsets")));
+        assert!(Picture::new(&[1, 0]).is_err());
+    }
+
+    #[test]
+    fn columns_outside_of_the_lump_are_errors() {
+        let mut data = patch(4, 0, 0, &[&[(0, &[1])]]);
+        data[8..12].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(!Picture::is_valid_header(&data));
+        assert!(Picture::new(&data).is_err());
+    }
+}
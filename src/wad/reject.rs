@@ -0,0 +1,86 @@
+use super::*;
+
+/// The REJECT lump, one bit per pair of sectors set when nothing in the first sector can
+/// possibly see into the second, letting line of sight checks be skipped
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct RejectTable {
+    pub sector_count: usize,
+    /// The lump as stored, like vanilla it's used as is whether it's too short or too long
+    pub data: RawData,
+}
+
+impl RejectTable {
+    pub fn new(data: &[u8], sector_count: usize) -> Self {
+        Self {
+            sector_count,
+            data: data.to_vec(),
+        }
+    }
+
+    /// One bit per pair of sectors, rounded up to a whole byte
+    pub fn table_size(sector_count: usize) -> usize {
+        (sector_count * sector_count).div_ceil(8)
+    }
+
+    /// Whether the lump is the size the map's sector count needs
+    pub fn is_expected_size(&self) -> bool {
+        self.data.len() == Self::table_size(self.sector_count)
+    }
+
+    /// Whether anything in `from_sector` could see into `to_sector`, sector pairs past the
+    /// end of a short table (or outside of the map) are never rejected
+    pub fn can_see(&self, from_sector: usize, to_sector: usize) -> bool {
+        if from_sector >= self.sector_count || to_sector >= self.sector_count {
+            return true;
+        }
+        let bit = from_sector * self.sector_count + to_sector;
+        self.data.get(bit / 8).is_none_or(|byte| byte & (1 << (bit % 8)) == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three sectors where 0 can't see 2, and 2 can see neither 1 nor itself
+    fn reject_table(data: &[u8]) -> RejectTable {
+        RejectTable::new(data, 3)
+    }
+
+    #[test]
+    fn bits_are_read_row_by_row_from_the_low_bit() {
+        let reject = reject_table(&[0b1000_0100, 0b0000_0001]);
+        let visible: Vec<Vec<bool>> = (0..3).map(|from| (0..3).map(|to| reject.can_see(from, to)).collect()).collect();
+        assert_eq!(visible, [
+            [true, true, false],
+            [true, true, true],
+            [true, false, false],
+        ]);
+    }
+
+    #[test]
+    fn sectors_outside_of_the_table_are_never_rejected() {
+        let reject = reject_table(&[0xFF, 0xFF]);
+        assert!(!reject.can_see(2, 2));
+        assert!(reject.can_see(3, 0));
+        assert!(reject.can_see(0, 3));
+        assert!(reject.can_see(usize::MAX, usize::MAX));
+
+        // Like vanilla a short lump only rejects the pairs it has bits for
+        let short = reject_table(&[0xFF]);
+        assert!(!short.can_see(2, 1));
+        assert!(short.can_see(2, 2));
+        assert!(RejectTable::default().can_see(0, 0));
+    }
+
+    #[test]
+    fn tables_are_rounded_up_to_a_whole_byte() {
+        assert_eq!(RejectTable::table_size(0), 0);
+        assert_eq!(RejectTable::table_size(3), 2);
+        assert_eq!(RejectTable::table_size(8), 8);
+
+        assert!(reject_table(&[0, 0]).is_expected_size());
+        assert!(!reject_table(&[0]).is_expected_size());
+        assert!(!reject_table(&[0, 0, 0]).is_expected_size());
+    }
+}
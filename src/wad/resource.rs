@@ -0,0 +1,275 @@
+use super::*;
+
+use std::collections::HashSet;
+use std::path::Path;
+
+/// A file within a zip archive or directory, `path` is relative to the root of the
+/// resource and always separated by `/`
+#[derive(Debug)]
+pub struct ResourceFile {
+    pub path: String,
+    pub data: RawData,
+}
+
+/// Where a resource file's lump goes, decided by the top level directory it's in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ResourceNamespace {
+    Global,
+    Map,
+    Marked(&'static str, &'static str),
+}
+
+impl ResourceNamespace {
+    const SPRITES: Self = Self::Marked("S_START", "S_END");
+    const FLATS: Self = Self::Marked("F_START", "F_END");
+    const PATCHES: Self = Self::Marked("P_START", "P_END");
+    const TEXTURES: Self = Self::Marked("TX_START", "TX_END");
+
+    fn from_path(path: &str) -> Self {
+        let Some((directory, _)) = path.split_once('/') else { return Self::Global };
+        match directory.to_ascii_lowercase().as_str() {
+            "sprites" => Self::SPRITES,
+            "flats" => Self::FLATS,
+            "patches" => Self::PATCHES,
+            // Textures are whole images, usually PNGs, rather than pictures
+            "textures" => Self::TEXTURES,
+            "maps" if path.to_ascii_lowercase().ends_with(".wad") => Self::Map,
+            _ => Self::Global,
+        }
+    }
+}
+
+impl Wad {
+    /// Load a wad, a zip archive (pk3) or a directory laid out like one
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        if path.is_dir() {
+            return Self::from_directory(path);
+        }
+        let data = fs::read(path).map_err(|err| Error::FileOpen(format!("{}: {err}", path.display())))?;
+        if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+            Self::from_archive(path, &data)
+        } else {
+            Ok(Self {
+                path: path.to_path_buf(),
+                meta: WadMeta::new(&data)?,
+                raw_data: data,
+            })
+        }
+    }
+
+    /// Mount a zip archive, see `from_resource_files` for how its paths become lumps
+    pub fn from_archive(path: &Path, data: &[u8]) -> Result<Self, Error> {
+        let error = |err: zip::result::ZipError| Error::Decoding(path.display().to_string(), err.to_string());
+        let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(error)?;
+
+        let mut files = Vec::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(error)?;
+            if file.is_dir() {
+                continue;
+            }
+            // The size in the header isn't trusted to allocate for
+            let mut data = RawData::new();
+            file.read_to_end(&mut data)
+                .map_err(|err| Error::Decoding(path.display().to_string(), format!("{}: {err}", file.name())))?;
+            files.push(ResourceFile { path: file.name().to_string(), data });
+        }
+        Self::from_resource_files(path, files)
+    }
+
+    /// Mount a directory, see `from_resource_files` for how its paths become lumps.
+    /// Directories are only visited once however they're linked to
+    pub fn from_directory(path: &Path) -> Result<Self, Error> {
+        let mut files = Vec::new();
+        let mut directories = vec![path.to_path_buf()];
+        let mut visited = HashSet::new();
+        while let Some(directory) = directories.pop() {
+            let canonical = directory.canonicalize()
+                .map_err(|err| Error::FileRead(format!("{}: {err}", directory.display())))?;
+            if !visited.insert(canonical) {
+                continue;
+            }
+            let entries = fs::read_dir(&directory)
+                .map_err(|err| Error::FileRead(format!("{}: {err}", directory.display())))?;
+            for entry in entries {
+                let entry_path = entry.map_err(|err| Error::FileRead(format!("{}: {err}", directory.display())))?.path();
+                if entry_path.is_dir() {
+                    directories.push(entry_path);
+                    continue;
+                }
+                let relative = entry_path.strip_prefix(path).unwrap_or(&entry_path)
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let data = fs::read(&entry_path)
+                    .map_err(|err| Error::FileRead(format!("{}: {err}", entry_path.display())))?;
+                files.push(ResourceFile { path: relative, data });
+            }
+        }
+        Self::from_resource_files(path, files)
+    }
+
+    /// Build a wad from the files of a resource, each file becomes a lump named after its
+    /// file name, files under `sprites/`, `flats/`, `patches/` and `textures/` go between
+    /// that namespace's markers, wads under `maps/` have their lumps added as they are under
+    /// a marker named after the file and everything else (`music/`, `sounds/`, files at the
+    /// root...) is added as a global lump
+    pub fn from_resource_files(path: &Path, mut files: Vec<ResourceFile>) -> Result<Self, Error> {
+        files.sort_by_cached_key(|file| (ResourceNamespace::from_path(&file.path), file.path.to_ascii_lowercase()));
+
+        let mut writer = WadWriter::new(Identification::PWAD);
+        let mut current = ResourceNamespace::Global;
+        for file in files {
+            let namespace = ResourceNamespace::from_path(&file.path);
+            if namespace != current {
+                if let ResourceNamespace::Marked(_, end) = current {
+                    writer.add_lump(end, RawData::new())?;
+                }
+                if let ResourceNamespace::Marked(start, _) = namespace {
+                    writer.add_lump(start, RawData::new())?;
+                }
+                current = namespace;
+            }
+
+            if namespace == ResourceNamespace::Map {
+                let Some(name) = Self::resource_lump_name(&file.path) else { continue };
+                let meta = WadMeta::new(&file.data)
+                    .map_err(|err| Error::Decoding(format!("{}/{}", path.display(), file.path), err.to_string()))?;
+                // As in ZDoom the map is named after the file rather than its own marker
+                if !Map::is_marker(&meta.lumps, 0) {
+                    eprintln!("warning: skipping '{}', it doesn't start with a map", file.path);
+                    continue;
+                }
+                writer.add_lump(&name, RawData::new())?;
+                for lump in meta.lumps.iter().skip(1) {
+                    writer.add_lump(&lump.name, lump.data(&file.data)?.to_vec())?;
+                }
+            } else if let Some(name) = Self::resource_lump_name(&file.path) {
+                writer.add_lump(&name, file.data)?;
+            }
+        }
+        if let ResourceNamespace::Marked(_, end) = current {
+            writer.add_lump(end, RawData::new())?;
+        }
+
+        let raw_data = writer.to_bytes()?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            meta: WadMeta::new(&raw_data)?,
+            raw_data,
+        })
+    }
+
+    /// The file name up to its first `.`, upper cased and cut down to 8 characters,
+    /// dot files and the like have no lump name. Files whose names aren't ASCII can't be
+    /// lumps and are skipped with a warning, longer names are cut down with one
+    fn resource_lump_name(path: &str) -> Option<String> {
+        let file_name = path.rsplit('/').next()?;
+        let stem = file_name.split('.').next()?;
+        if stem.is_empty() {
+            return None;
+        }
+        if !stem.is_ascii() {
+            eprintln!("warning: skipping '{path}', lump names can only be ASCII");
+            return None;
+        }
+        let name = stem[..stem.len().min(8)].to_ascii_uppercase();
+        if stem.len() > 8 {
+            eprintln!("warning: '{path}' is loaded as {name}, lump names are at most 8 characters");
+        }
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_maps::*;
+
+    fn file(path: &str, data: &[u8]) -> ResourceFile {
+        ResourceFile { path: path.to_string(), data: data.to_vec() }
+    }
+
+    fn names(wad: &Wad) -> Vec<&str> {
+        wad.meta.lumps.iter().map(|lump| lump.name.as_str()).collect()
+    }
+
+    #[test]
+    fn directories_decide_the_namespace() {
+        let wad = Wad::from_resource_files(Path::new("test.pk3"), vec![
+            file("textures/wall.png", b"png"),
+            file("patches/wall1.lmp", b"patch"),
+            file("flats/floor.lmp", b"flat"),
+            file("sprites/trooa1.lmp", b"sprite"),
+            file("music/d_e1m1.mus", b"music"),
+            file("mapinfo.txt", b"info"),
+            file(".hidden", b""),
+        ]).unwrap();
+        assert_eq!(names(&wad), [
+            "MAPINFO", "D_E1M1",
+            "F_START", "FLOOR", "F_END",
+            "P_START", "WALL1", "P_END",
+            "S_START", "TROOA1", "S_END",
+            "TX_START", "WALL", "TX_END",
+        ]);
+
+        let mut namespace = Namespace::default();
+        namespace.add_wad("test.pk3", &wad.meta.lumps);
+        assert!(namespace.textures.contains_key("WALL"));
+        assert!(!namespace.patches.contains_key("WALL"));
+    }
+
+    #[test]
+    fn names_lumps_cant_have_are_skipped_or_cut_down() {
+        let wad = Wad::from_resource_files(Path::new("test.pk3"), vec![
+            file("sounds/dsdoorcl\u{e9}.wav", b"sound"),
+            file("sounds/dsdoorclose.wav", b"sound"),
+            file("sounds/dspistol.wav", b"sound"),
+        ]).unwrap();
+        assert_eq!(names(&wad), ["DSDOORCL", "DSPISTOL"]);
+    }
+
+    #[test]
+    fn maps_are_named_after_their_file() {
+        let wad = Wad::from_resource_files(Path::new("test.pk3"), vec![
+            file("maps/map07.wad", &wad_data(&square_room())),
+            file("maps/notamap.wad", &wad_data(&[("DEMO1", vec![1])])),
+        ]).unwrap();
+        assert_eq!(names(&wad)[0], "MAP07");
+        assert_eq!(wad.meta.lumps.len(), square_room().len());
+        assert!(Map::new(&wad.meta.lumps, &wad.raw_data, 0).is_ok());
+    }
+
+    #[test]
+    fn archives_are_mounted() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        writer.add_directory("flats/", options).unwrap();
+        writer.start_file("flats/floor.lmp", options).unwrap();
+        writer.write_all(&[7; 4096]).unwrap();
+        writer.start_file("maps/e1m1.wad", options).unwrap();
+        writer.write_all(&wad_data(&square_room())).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        let wad = Wad::from_archive(Path::new("test.pk3"), &data).unwrap();
+        assert_eq!(&names(&wad)[..2], ["E1M1", "THINGS"]);
+        assert_eq!(&names(&wad)[wad.meta.lumps.len() - 3..], ["F_START", "FLOOR", "F_END"]);
+        let floor = &wad.meta.lumps[wad.meta.lumps.len() - 2];
+        assert_eq!(floor.data(&wad.raw_data).unwrap(), [7; 4096]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_links_are_only_followed_once() {
+        let root = std::env::temp_dir().join(format!("resource-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("flats")).unwrap();
+        fs::write(root.join("flats/floor.lmp"), [1; 4096]).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("flats/loop")).unwrap();
+
+        let wad = Wad::from_directory(&root);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(names(&wad.unwrap()), ["F_START", "FLOOR", "F_END"]);
+    }
+}
@@ -0,0 +1,174 @@
+use super::*;
+
+#[derive(Debug, BinRead)]
+#[br(little)]
+struct SoundHeader {
+    format: u16,
+    sample_rate: u16,
+    sample_count: u32,
+}
+
+#[derive(Debug, BinWrite)]
+#[bw(little, magic = b"RIFF")]
+struct WavHeader {
+    file_size: u32,
+    #[bw(magic = b"WAVEfmt ")]
+    fmt_size: u32,
+    audio_format: u16,
+    channels: u16,
+    sample_rate: u32,
+    byte_rate: u32,
+    block_align: u16,
+    bits_per_sample: u16,
+    #[bw(magic = b"data")]
+    data_size: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleBits {
+    Eight,
+    Sixteen,
+}
+
+/// A decoded DMX digital sound (DS*) lump, samples are unsigned 8-bit mono PCM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sound {
+    pub format: u16,
+    pub sample_rate: u16,
+    pub samples: Vec<u8>,
+}
+
+impl Sound {
+    pub const FORMAT: u16 = 3;
+    /// DMX pads the start and end of the samples by repeating the first and last sample
+    pub const PADDING: usize = 16;
+
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let error = |reason: String| Error::Decoding("sound".to_string(), reason);
+        let header = SoundHeader::read(&mut Cursor::new(data))
+            .map_err(|err| error(err.root_cause().to_string()))?;
+        if header.format != Self::FORMAT {
+            return Err(error(format!("format {} is not a DMX digital sound", header.format)));
+        }
+
+        let sample_count = header.sample_count as usize;
+        let samples = data.get(8..8 + sample_count).ok_or_else(|| error(format!(
+            "header claims {sample_count} samples but only {} bytes follow it", data.len() - 8
+        )))?;
+        // Like vanilla, sounds too short to have been padded are taken as is
+        let samples = if sample_count > Self::PADDING * 3 {
+            &samples[Self::PADDING..sample_count - Self::PADDING]
+        } else {
+            samples
+        };
+
+        Ok(Self {
+            format: header.format,
+            sample_rate: header.sample_rate,
+            samples: samples.to_vec(),
+        })
+    }
+
+    /// Samples as signed 16-bit PCM
+    pub fn to_pcm16(&self) -> Vec<i16> {
+        self.samples.iter().map(|sample| ((*sample as i16) - 128) << 8).collect()
+    }
+
+    /// Duration in seconds
+    pub fn duration(&self) -> f32 {
+        if self.sample_rate == 0 { 0.0 } else { self.samples.len() as f32 / self.sample_rate as f32 }
+    }
+
+    /// A complete mono WAV file at the sound's sample rate
+    pub fn to_wav(&self, bits: SampleBits) -> Result<RawData, Error> {
+        let data: RawData = match bits {
+            SampleBits::Eight => self.samples.clone(),
+            SampleBits::Sixteen => self.to_pcm16().iter().flat_map(|sample| sample.to_le_bytes()).collect(),
+        };
+        let bytes_per_sample: u16 = match bits {
+            SampleBits::Eight => 1,
+            SampleBits::Sixteen => 2,
+        };
+        let header = WavHeader {
+            file_size: 36 + data.len() as u32,
+            fmt_size: 16,
+            audio_format: 1, // PCM
+            channels: 1,
+            sample_rate: self.sample_rate as u32,
+            byte_rate: self.sample_rate as u32 * bytes_per_sample as u32,
+            block_align: bytes_per_sample,
+            bits_per_sample: bytes_per_sample * 8,
+            data_size: data.len() as u32,
+        };
+
+        let mut cursor = Cursor::new(RawData::with_capacity(44 + data.len()));
+        header.write(&mut cursor)
+            .map_err(|err| Error::Writing(err.root_cause().to_string()))?;
+        let mut wav = cursor.into_inner();
+        wav.extend(data);
+        Ok(wav)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sound_data(format: u16, sample_rate: u16, samples: &[u8]) -> RawData {
+        let mut data = format.to_le_bytes().to_vec();
+        data.extend(sample_rate.to_le_bytes());
+        data.extend((samples.len() as u32).to_le_bytes());
+        data.extend(samples);
+        data
+    }
+
+    #[test]
+    fn padding_is_stripped_from_long_sounds() {
+        let samples: Vec<u8> = (0..100).collect();
+        let sound = Sound::new(&sound_data(3, 11025, &samples)).unwrap();
+        assert_eq!(sound.sample_rate, 11025);
+        assert_eq!(sound.samples, samples[16..84]);
+
+        let sound = Sound::new(&sound_data(3, 11025, &samples[..48])).unwrap();
+        assert_eq!(sound.samples, samples[..48]);
+    }
+
+    #[test]
+    fn other_formats_and_missing_samples_are_errors() {
+        assert!(matches!(Sound::new(&sound_data(0, 11025, &[128; 4])), Err(Error::Decoding(_, reason)) if reason.contains("format 0")));
+        let mut data = sound_data(3, 11025, &[128; 64]);
+        data.truncate(40);
+        assert!(matches!(Sound::new(&data), Err(Error::Decoding(_, reason)) if reason.contains("64 samples")));
+        assert!(Sound::new(&[3, 0]).is_err());
+    }
+
+    #[test]
+    fn samples_convert_to_signed_16_bit() {
+        let sound = Sound { format: 3, sample_rate: 4, samples: vec![0, 128, 255, 130] };
+        assert_eq!(sound.to_pcm16(), [-32768, 0, 32512, 512]);
+        assert_eq!(sound.duration(), 1.0);
+        assert_eq!(Sound { sample_rate: 0, ..sound }.duration(), 0.0);
+    }
+
+    #[test]
+    fn wavs_are_mono_pcm_at_the_sound_rate() {
+        let sound = Sound { format: 3, sample_rate: 11025, samples: vec![0, 128, 255] };
+        let wav = sound.to_wav(SampleBits::Eight).unwrap();
+        let u16_at = |wav: &[u8], at: usize| u16::from_le_bytes([wav[at], wav[at + 1]]);
+        let u32_at = |wav: &[u8], at: usize| u32::from_le_bytes(wav[at..at + 4].try_into().unwrap());
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32_at(&wav, 4), 36 + 3);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!((u32_at(&wav, 16), u16_at(&wav, 20), u16_at(&wav, 22)), (16, 1, 1));
+        assert_eq!((u32_at(&wav, 24), u32_at(&wav, 28)), (11025, 11025));
+        assert_eq!((u16_at(&wav, 32), u16_at(&wav, 34)), (1, 8));
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(&wav, 40), 3);
+        assert_eq!(wav[44..], [0, 128, 255]);
+
+        let wav = sound.to_wav(SampleBits::Sixteen).unwrap();
+        assert_eq!((u32_at(&wav, 28), u16_at(&wav, 32), u16_at(&wav, 34)), (22050, 2, 16));
+        assert_eq!(u32_at(&wav, 40), 6);
+        assert_eq!(wav[44..], [0x00, 0x80, 0x00, 0x00, 0x00, 0x7F]);
+    }
+}
@@ -6,7 +6,13 @@ use super::*;
 
 use binrw::{BinReaderExt, binrw, BinRead, io::Cursor, args};
 
-const DOOMMAPLUMPLENGTH: usize = 11;
+/// Lumps that can follow a map marker, the first lump not in this list (or starting with `GL_`)
+/// marks the end of the map
+const MAP_LUMP_NAMES: [&str; 16] = [
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS",
+    "NODES", "SECTORS", "REJECT", "BLOCKMAP", "BEHAVIOR", "SCRIPTS",
+    "TEXTMAP", "ZNODES", "DIALOGUE", "ENDMAP",
+];
 
 pub type Lumps = Vec<Lump>;
 #[derive(Debug, BinRead)]
@@ -20,8 +26,31 @@ pub struct WadMeta {
 }
 
 impl WadMeta {
-    pub fn new(data: &RawData) -> Self {
-        WadMeta::read(&mut Cursor::new(data)).unwrap()
+    pub fn new(data: &RawData) -> Result<Self, Error> {
+        let mut meta = WadMeta::read(&mut Cursor::new(data))
+            .map_err(|err| Self::unpack_error(data, err))?;
+        for (index, lump) in meta.lumps.iter_mut().enumerate() {
+            lump.index = index;
+        }
+        LumpKind::classify(&mut meta.lumps, data);
+        Ok(meta)
+    }
+
+    /// Work out whether the header or the directory it points at was bad
+    fn unpack_error(data: &RawData, err: binrw::Error) -> UnpackError {
+        if data.len() < 12 {
+            return UnpackError::Headers(format!("wad is only {} bytes long", data.len()));
+        }
+        let lump_count = i32::from_le_bytes(data[4..8].try_into().unwrap());
+        let dir_offset = i32::from_le_bytes(data[8..12].try_into().unwrap());
+        let dir_end = dir_offset as i64 + lump_count as i64 * 16;
+        if lump_count < 0 || dir_offset < 0 || dir_end > data.len() as i64 {
+            UnpackError::Directory(format!(
+                "{lump_count} lumps at offset {dir_offset} need {dir_end} bytes but the wad is {} bytes long", data.len()
+            ))
+        } else {
+            UnpackError::Headers(err.root_cause().to_string())
+        }
     }
 
 }
@@ -46,6 +75,7 @@ pub type LineDefVertexes = Vec<P1P2>;
 #[derive(Debug)]
 pub struct Map {
     pub name: String,
+    pub format: MapFormat,
     pub things: Vec<Thing>,
     pub line_defs: Vec<LineDef>,
     pub side_defs: Vec<SideDef>,
@@ -54,25 +84,47 @@ pub struct Map {
     pub sub_sectors: Vec<SubSector>,
     pub nodes: Vec<Node>,
     pub sectors: Vec<Sector>,
-    pub reject: Vec<Reject>,
-    pub block_map: Vec<BlockMap>,
+    pub reject: RejectTable,
+    pub block_map: Option<BlockMap>,
+    /// Nodes built by glBSP, found in the `GL_<MAP>` lumps following the map
+    pub gl_nodes: Option<GlNodes>,
+    /// Hexen format maps' compiled ACS scripts, kept as is
+    pub behavior: Option<RawData>,
+    /// The parsed TEXTMAP of UDMF maps, which keeps the unrounded coordinates and any
+    /// properties the binary records have no room for
+    pub udmf: Option<Udmf>,
+    pub node_source: NodeSource,
+    /// The lumps following the marker as they were read, what the records can't say (or
+    /// weren't read from) is written back from these
+    pub lumps: Vec<(String, RawData)>,
     pub map_points: OnceLock<Points>,
     pub map_bounds: OnceLock<P1P2>,
     pub line_defs_to_vertexes: OnceLock<LineDefVertexes>,
 }
 
+/// Where a map's segs, subsectors and nodes came from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NodeSource {
+    /// SEGS, SSECTORS and NODES
+    Lumps,
+    Extended(ExtendedFormat),
+    /// There were none so they were built when the map was loaded
+    Built,
+}
 
 type Offset = usize;
 
-impl std::convert::From<(&Vec<Lump>, &RawData, Offset)> for Map {
-    fn from((wad_lumps, raw_data, offset): (&Vec<Lump>, &RawData, Offset)) -> Self {
+impl std::convert::TryFrom<(&Vec<Lump>, &RawData, Offset)> for Map {
+    type Error = Error;
+
+    fn try_from((wad_lumps, raw_data, offset): (&Vec<Lump>, &RawData, Offset)) -> Result<Self, Self::Error> {
         Self::new(wad_lumps, raw_data, offset)
     }
 }
 
 #[derive(Debug)]
 pub struct SegsToDraw {
-    pub sub_sector_id: u16,
+    pub sub_sector_id: u32,
     pub segments: Vec<Segment>
 }
 
@@ -80,23 +132,152 @@ impl Map {
     /// Returns a new instance of Map, deserializaion of map data happens at this point,
     /// which is why you have to pass in the raw_data, wad_lumps are directory of offsets
     /// and no more
-    pub fn new(wad_lumps: &Vec<Lump>, raw_data: &RawData, offset: Offset) -> Self {
-        Map {
-            name: wad_lumps[offset].name.clone(),
-            things: wad_lumps[offset + 1].deserialize(raw_data),
-            line_defs: wad_lumps[offset + 2].deserialize(raw_data),
-            side_defs: wad_lumps[offset + 3].deserialize(raw_data),
-            vertexes: wad_lumps[offset + 4].deserialize(raw_data),
-            segments: wad_lumps[offset + 5].deserialize(raw_data),
-            sub_sectors: wad_lumps[offset + 6].deserialize(raw_data),
-            nodes: wad_lumps[offset + 7].deserialize(raw_data),
-            sectors: wad_lumps[offset + 8].deserialize(raw_data),
-            reject: wad_lumps[offset + 9].deserialize(raw_data),
-            block_map: wad_lumps[offset + 10].deserialize(raw_data),
+    pub fn new(wad_lumps: &Vec<Lump>, raw_data: &RawData, offset: Offset) -> Result<Self, Error> {
+        let name = wad_lumps.get(offset)
+            .ok_or_else(|| Error::Reader(format!("no map marker at lump {offset}")))?
+            .name.clone();
+        let map_lumps = Self::map_lumps(wad_lumps, offset);
+
+        let required = |lump_name: &str| map_lumps.get(lump_name)
+            .ok_or_else(|| Error::MissingLump(name.clone(), lump_name.to_string()));
+
+        let behavior = map_lumps.get("BEHAVIOR").map(|lump| lump.data(raw_data)).transpose()?;
+        let udmf = map_lumps.get("TEXTMAP").map(|lump| Udmf::new(lump.data(raw_data)?)).transpose()?;
+
+        let (format, (things, line_defs, side_defs, vertexes, sectors)) = match &udmf {
+            Some(udmf) => (udmf.namespace.map_format(), udmf.to_records()?),
+            None => {
+                let format = if behavior.is_some() { MapFormat::Hexen } else { MapFormat::Doom };
+                // Lumps are classified without knowing the map's format so their counts assume Doom's
+                (format, (
+                    required("THINGS")?.deserialize_with(raw_data, format.thing_size(), |id| (id, format))?,
+                    required("LINEDEFS")?.deserialize_with(raw_data, format.line_def_size(), |id| (id, format))?,
+                    required("SIDEDEFS")?.deserialize(raw_data)?,
+                    required("VERTEXES")?.deserialize(raw_data)?,
+                    required("SECTORS")?.deserialize(raw_data)?,
+                ))
+            }
+        };
+        let sector_count = sectors.len();
+
+        // Extended nodes replace the segs, subsectors and nodes and add to the vertexes,
+        // leaving SEGS (and for XNOD/ZNOD, SSECTORS) empty, UDMF maps keep them in ZNODES.
+        // Maps straight out of an editor may have no nodes at all, in which case they're
+        // built here
+        let data = |lump_name: &str| map_lumps.get(lump_name).map(|lump| lump.data(raw_data)).transpose();
+        let nodes_data = data("NODES")?.unwrap_or_default();
+        let sub_sectors_data = data("SSECTORS")?.unwrap_or_default();
+        let z_nodes_data = data("ZNODES")?.unwrap_or_default();
+        let segments_data = data("SEGS")?.unwrap_or_default();
+        let extended = if ExtendedFormat::from_nodes(nodes_data).is_some() {
+            Some(ExtendedNodes::new(nodes_data, &vertexes, &line_defs)?)
+        } else if ExtendedFormat::from_sub_sectors(sub_sectors_data).is_some() {
+            Some(ExtendedNodes::new(sub_sectors_data, &vertexes, &line_defs)?)
+        } else if ExtendedFormat::from_nodes(z_nodes_data).or_else(|| ExtendedFormat::from_sub_sectors(z_nodes_data)).is_some() {
+            // ZNODES can hold either kind
+            Some(ExtendedNodes::new(z_nodes_data, &vertexes, &line_defs)?)
+        } else {
+            None
+        };
+        let (node_source, (vertexes, segments, sub_sectors, nodes)) = match extended {
+            Some(extended) => (
+                NodeSource::Extended(extended.format),
+                (extended.map_vertexes(), extended.segments, extended.sub_sectors, extended.nodes),
+            ),
+            None if segments_data.is_empty() || sub_sectors_data.is_empty() => (NodeSource::Lumps, (vertexes, Vec::new(), Vec::new(), Vec::new())),
+            None => (NodeSource::Lumps, (
+                vertexes,
+                required("SEGS")?.deserialize(raw_data)?,
+                required("SSECTORS")?.deserialize(raw_data)?,
+                required("NODES")?.deserialize(raw_data)?,
+            )),
+        };
+        // A single subsector needs no nodes, any more can't be reached without them
+        let (node_source, (vertexes, segments, sub_sectors, nodes)) = if sub_sectors.is_empty() || (nodes.is_empty() && sub_sectors.len() > 1) {
+            let built = NodeBuilder::build(&vertexes, &line_defs, &side_defs, &sectors);
+            (NodeSource::Built, (built.vertexes, built.segments, built.sub_sectors, built.nodes))
+        } else {
+            (node_source, (vertexes, segments, sub_sectors, nodes))
+        };
+
+        Ok(Map {
+            things,
+            format,
+            behavior: behavior.map(<[u8]>::to_vec),
+            udmf,
+            line_defs,
+            side_defs,
+            vertexes,
+            segments,
+            sub_sectors,
+            nodes,
+            sectors,
+            reject: RejectTable::new(
+                map_lumps.get("REJECT").map(|l| l.data(raw_data)).transpose()?.unwrap_or_default(),
+                sector_count,
+            ),
+            // A bad BLOCKMAP is dropped to be rebuilt like a missing one
+            block_map: map_lumps.get("BLOCKMAP")
+                .filter(|l| l.size > 0)
+                .and_then(|l| BlockMap::new(l.data(raw_data).ok()?).ok()),
+            // GL nodes are an optional extra, the validator reports any that can't be read
+            gl_nodes: Self::decode_gl_nodes(&map_lumps, raw_data).ok().flatten(),
+            node_source,
+            lumps: wad_lumps[offset + 1..Self::map_end(wad_lumps, offset)].iter()
+                .map(|lump| Ok((lump.name.clone(), lump.data(raw_data)?.to_vec())))
+                .collect::<Result<_, Error>>()?,
+            name,
             map_points: OnceLock::new(),
             map_bounds: OnceLock::new(),
             line_defs_to_vertexes : OnceLock::new(),
+        })
+    }
+
+    /// GL nodes are only used when all four of their lumps are present
+    pub fn decode_gl_nodes(map_lumps: &HashMap<&str, &Lump>, raw_data: &RawData) -> Result<Option<GlNodes>, Error> {
+        let data = |lump_name: &str| map_lumps.get(lump_name).map(|lump| lump.data(raw_data));
+        match (data("GL_VERT"), data("GL_SEGS"), data("GL_SSECT"), data("GL_NODES")) {
+            (Some(vertexes), Some(segments), Some(sub_sectors), Some(nodes)) =>
+                Ok(Some(GlNodes::new(vertexes?, segments?, sub_sectors?, nodes?)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether anything in `from_sector` could possibly see into `to_sector`
+    pub fn can_see(&self, from_sector: usize, to_sector: usize) -> bool {
+        self.reject.can_see(from_sector, to_sector)
+    }
+
+    /// Is the lump at `offset` a map marker, i.e. is it followed by a map's THINGS lump
+    /// or for UDMF maps its TEXTMAP
+    pub fn is_marker(wad_lumps: &Lumps, offset: Offset) -> bool {
+        wad_lumps.get(offset + 1).is_some_and(|lump| lump.name == "THINGS" || lump.name == "TEXTMAP")
+    }
+
+    /// Collects the lumps belonging to the map whose marker is at `offset` keyed by name,
+    /// in whatever order they appear
+    pub fn map_lumps(wad_lumps: &Lumps, offset: Offset) -> HashMap<&str, &Lump> {
+        wad_lumps[offset + 1..Self::map_end(wad_lumps, offset)].iter()
+            .map(|lump| (lump.name.as_str(), lump))
+            .collect()
+    }
+
+    /// Index of the first lump after the map whose marker is at `offset`, i.e. the first lump
+    /// that can't be part of a map
+    pub fn map_end(wad_lumps: &Lumps, offset: Offset) -> Offset {
+        // Everything between a UDMF map's TEXTMAP and ENDMAP belongs to it, whatever its name,
+        // as long as the ENDMAP comes before the next map
+        if wad_lumps.get(offset + 1).is_some_and(|lump| lump.name == "TEXTMAP") {
+            let end = (offset + 1..wad_lumps.len())
+                .find(|&index| wad_lumps[index].name == "ENDMAP" || Self::is_marker(wad_lumps, index));
+            if let Some(end) = end.filter(|&index| wad_lumps[index].name == "ENDMAP") {
+                return end + 1;
+            }
         }
+        offset + 1 + wad_lumps.iter()
+            .skip(offset + 1)
+            .take_while(|lump| MAP_LUMP_NAMES.contains(&lump.name.as_str()) || lump.name.starts_with("GL_"))
+            .count()
     }
 
 
@@ -141,15 +322,15 @@ impl Map {
     
     /// From a list of nodes generated by traversing the BSP via traverse_bsp, return a list of Segments oredered
     /// from the player location outwards
-    pub fn segs_from_nodes(&self, nodes: &Vec<Node>, player_pos: (i16, i16)) -> Vec<SegsToDraw> {
-        nodes.iter().rev().fold( (Vec::new(), Vec::new()), |(visited, segments): (Vec<u16>, Vec<SegsToDraw>), node: &Node| {
+    pub fn segs_from_nodes(&self, nodes: &Vec<Node>, player_pos: (Fixed, Fixed)) -> Vec<SegsToDraw> {
+        nodes.iter().rev().fold( (Vec::new(), Vec::new()), |(visited, segments): (Vec<u32>, Vec<SegsToDraw>), node: &Node| {
             self.recurse_node_from_list(segments, node, visited, player_pos)
         }).1
     }
 
     /// Recursivly traverse the tree, this specific function ensures that visted nodes are never revisted
     #[inline]
-    fn recurse_node_from_list(&self, mut segments: Vec<SegsToDraw>, node: &Node, mut visited: Vec<u16>, player_pos: (i16, i16)) -> (Vec<u16>, Vec<SegsToDraw>) {
+    fn recurse_node_from_list(&self, mut segments: Vec<SegsToDraw>, node: &Node, mut visited: Vec<u32>, player_pos: (Fixed, Fixed)) -> (Vec<u32>, Vec<SegsToDraw>) {
         let (b_is_ssector, f_is_ssector) = node.children_are_sub_sectors();
         if node.is_in_back_side(player_pos) {
             if !visited.contains(&node.back_child_id) {
@@ -175,7 +356,7 @@ impl Map {
     }
 
     // Either ends the recursion by pushing a sub_sector or continues ir
-    fn push_or_traverse_child(&self, segments: &mut Vec<SegsToDraw>, is_ssector: bool, child_id: u16, player_pos: (i16, i16)) {
+    fn push_or_traverse_child(&self, segments: &mut Vec<SegsToDraw>, is_ssector: bool, child_id: u32, player_pos: (Fixed, Fixed)) {
         if is_ssector { 
             segments.push(SegsToDraw {
                 sub_sector_id: child_id,
@@ -187,7 +368,7 @@ impl Map {
     }
 
     /// Recursivly traverse a subtree,
-    fn recurse_subtree(&self, segments: &mut Vec<SegsToDraw>,  node: &Node, player_pos: (i16, i16)){
+    fn recurse_subtree(&self, segments: &mut Vec<SegsToDraw>,  node: &Node, player_pos: (Fixed, Fixed)){
         let (b_is_ssector, f_is_ssector) = node.children_are_sub_sectors();
         if node.is_in_back_side(player_pos) {
             self.push_or_traverse_child(segments, b_is_ssector, node.back_child_id, player_pos);
@@ -205,12 +386,20 @@ impl Map {
     /// Traverses the BSP tree to the players location within it, then returns the node path traversed
     /// the root node is always first, the player's node is always last, returns as an iterator so you
     /// can iterate over the nodes or build a list by using collect
-    pub fn traverse_bsp<Returned>(&self, thing_pos: (i16, i16)) -> TreeTraverseIterator<Returned> {
-        TreeTraverseIterator::new(&self.nodes, (self.nodes.len() - 1) as u16, thing_pos)
+    pub fn traverse_bsp<Returned>(&self, thing_pos: (Fixed, Fixed)) -> TreeTraverseIterator<Returned> {
+        TreeTraverseIterator::new(&self.nodes, self.root_node_id(), thing_pos)
+    }
+
+    /// The root is the last node, a map with no nodes is a single subsector
+    pub fn root_node_id(&self) -> u32 {
+        match self.nodes.len() {
+            0 => SubSector::SUB_SECTOR_IDENTIFIER,
+            count => count as u32 - 1,
+        }
     }
 
     /// Same as traverse_bsp but can provide a node to start from
-    pub fn traverse_bsp_from<Returned>(&self, node_id: u16, thing_pos: (i16, i16)) -> TreeTraverseIterator<Returned> {
+    pub fn traverse_bsp_from<Returned>(&self, node_id: u32, thing_pos: (Fixed, Fixed)) -> TreeTraverseIterator<Returned> {
         TreeTraverseIterator::new(&self.nodes, node_id, thing_pos)
     }
 }
@@ -218,14 +407,14 @@ impl Map {
 pub type Nodes = Vec<Node>;
 pub struct TreeTraverseIterator<'t, Returned=Node> {
     nodes: &'t Vec<Node>,
-    thing_pos: (i16, i16),
-    current_node_id: u16,
+    thing_pos: (Fixed, Fixed),
+    current_node_id: u32,
     finished: bool,
     _return: PhantomData<Returned>
 }
 
 impl<'t, Returned> TreeTraverseIterator<'t, Returned> {
-    pub fn new(nodes: &'t Nodes, current_node_id: u16, thing_pos: (i16, i16)) -> Self {
+    pub fn new(nodes: &'t Nodes, current_node_id: u32, thing_pos: (Fixed, Fixed)) -> Self {
         Self {
             nodes,
             thing_pos,
@@ -242,7 +431,7 @@ impl<'t> Iterator for TreeTraverseIterator<'t, Node> {
     fn next(&mut self) -> Option<Self::Item> {
         if self.finished { return None};
 
-        if self.current_node_id as u16 >= SubSector::SUB_SECTOR_IDENTIFIER {
+        if self.current_node_id >= SubSector::SUB_SECTOR_IDENTIFIER {
             None
         } else {
             let node = self.nodes.get(self.current_node_id  as usize).unwrap();
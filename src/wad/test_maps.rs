@@ -0,0 +1,193 @@
+//! Small wads built by hand for the tests, laid out byte by byte rather than with the
+//! records' own serialization or the `WadWriter` so reading and writing can be checked
+//! against them
+
+use super::*;
+
+pub type TestLumps = Vec<(&'static str, RawData)>;
+
+pub fn words(values: &[i32]) -> RawData {
+    values.iter().flat_map(|value| (*value as i16).to_le_bytes()).collect()
+}
+
+pub fn name(name: &str) -> RawData {
+    let mut bytes = name.as_bytes().to_vec();
+    bytes.resize(8, 0);
+    bytes
+}
+
+pub fn side_def(x_offset: i32, middle: &str, sector: i32) -> RawData {
+    [words(&[x_offset, 0]), name("-"), name("-"), name(middle), words(&[sector])].concat()
+}
+
+/// A 128 unit square room split down the middle into two subsectors
+pub fn square_room() -> TestLumps {
+    vec![
+        ("E1M1", RawData::new()),
+        ("THINGS", words(&[32, 32, 90, 1, 7, 96, 96, 180, 3004, 12])),
+        ("LINEDEFS", words(&[
+            0, 1, 1, 0, 0, 0, -1,
+            1, 2, 1, 0, 0, 1, -1,
+            2, 3, 1, 11, 0, 2, -1,
+            3, 0, 1, 0, 0, 3, -1,
+        ])),
+        ("SIDEDEFS", [
+            side_def(0, "STARTAN3", 0), side_def(0, "STARTAN3", 0),
+            side_def(0, "SW1START", 0), side_def(64, "STARTAN3", 0),
+        ].concat()),
+        ("VERTEXES", words(&[0, 0, 0, 128, 128, 128, 128, 0])),
+        ("SEGS", words(&[
+            0, 1, 16384, 0, 0, 0,
+            3, 0, -32768, 3, 0, 64,
+            1, 2, 0, 1, 0, 0,
+            2, 3, -16384, 2, 0, 0,
+        ])),
+        ("SSECTORS", words(&[2, 0, 2, 2])),
+        ("NODES", words(&[64, 0, 0, 128, 128, 0, 0, 64, 128, 0, 64, 128, -32767, -32768])),
+        ("SECTORS", [words(&[0, 128]), name("FLOOR4_8"), name("CEIL3_5"), words(&[160, 0, 0])].concat()),
+        ("REJECT", vec![0]),
+        ("BLOCKMAP", words(&[
+            -8, -8, 2, 2,
+            8, 12, 16, 16,
+            0, 0, 3, -1,
+            0, 3, 2, -1,
+            0, 0, 1, -1,
+        ])),
+    ]
+}
+
+/// The square room with glBSP V2 nodes after it, which split the top and bottom walls
+pub fn gl_room() -> TestLumps {
+    let mut lumps = square_room();
+    let mut vertexes = b"gNd2".to_vec();
+    for coordinate in [64i32, 0, 64, 128] {
+        vertexes.extend((coordinate << 16).to_le_bytes());
+    }
+    lumps.extend([
+        ("GL_E1M1", RawData::new()),
+        ("GL_VERT", vertexes),
+        // Vertexes with the top bit set are GL_VERT's, the partition is split into minisegs
+        ("GL_SEGS", words(&[
+            0, 1, 0, 0, -1,
+            1, -32767, 1, 0, -1,
+            -32767, -32768, -1, 0, -1,
+            -32768, 0, 3, 0, -1,
+            -32767, 2, 1, 0, -1,
+            2, 3, 2, 0, -1,
+            3, -32768, 3, 0, -1,
+            -32768, -32767, -1, 1, -1,
+        ])),
+        ("GL_SSECT", words(&[4, 0, 4, 4])),
+        ("GL_NODES", words(&[64, 0, 0, 128, 128, 0, 0, 64, 128, 0, 64, 128, -32767, -32768])),
+    ]);
+    lumps
+}
+
+/// The square room with its nodes in an XNOD stream in NODES, as ZDBSP writes them
+pub fn extended_room() -> TestLumps {
+    let lumps = with_lump(square_room(), "SEGS", RawData::new());
+    let lumps = with_lump(lumps, "SSECTORS", RawData::new());
+    with_lump(lumps, "NODES", xnod_nodes())
+}
+
+/// The square room's two subsectors and single node as an XNOD stream
+pub fn xnod_nodes() -> RawData {
+    let mut nodes = b"XNOD".to_vec();
+    for value in [4u32, 0, 2, 2, 2, 4] {
+        nodes.extend(value.to_le_bytes());
+    }
+    for (start, end, line_def) in [(0u32, 1u32, 0u16), (3, 0, 3), (1, 2, 1), (2, 3, 2)] {
+        nodes.extend(start.to_le_bytes());
+        nodes.extend(end.to_le_bytes());
+        nodes.extend(line_def.to_le_bytes());
+        nodes.push(0);
+    }
+    nodes.extend(1u32.to_le_bytes());
+    nodes.extend(words(&[64, 0, 0, 128, 128, 0, 0, 64, 128, 0, 64, 128]));
+    nodes.extend(0x8000_0001u32.to_le_bytes());
+    nodes.extend(0x8000_0000u32.to_le_bytes());
+    nodes
+}
+
+/// The square room in Hexen format, with a scripted switch and a thing with a tid
+pub fn hexen_room() -> TestLumps {
+    let lumps = with_lump(square_room(), "THINGS", words(&[
+        0, 32, 32, 0, 90, 1, 7, 0, 0, 0,
+        5, 96, 96, 16, 180, 3004, 12, 0, 0, 0,
+    ]));
+    let lumps = with_lump(lumps, "LINEDEFS", [
+        words(&[0, 1, 1]), vec![0; 6], words(&[0, -1]),
+        words(&[1, 2, 1]), vec![0; 6], words(&[1, -1]),
+        words(&[2, 3, 1 | 0x0400]), vec![80, 1, 0, 0, 0, 0], words(&[2, -1]),
+        words(&[3, 0, 1]), vec![0; 6], words(&[3, -1]),
+    ].concat());
+    let mut lumps = lumps;
+    lumps.push(("BEHAVIOR", b"ACS\0\x08\0\0\0\0\0\0\0".to_vec()));
+    lumps
+}
+
+/// The square room as UDMF, with its keys in the order an editor might write them and
+/// its nodes in ZNODES
+pub fn udmf_room() -> TestLumps {
+    let text = concat!(
+        "// Written by hand\n",
+        "namespace = \"zdoom\";\n",
+        "thing { y = 32.0; x = 32.0; type = 1; angle = 90; skill1 = true; }\n",
+        "vertex { x = 0.0; y = 0.0; }\n",
+        "vertex { x = 0.0; y = 128.0; }\n",
+        "vertex { x = 128.0; y = 128.0; }\n",
+        "vertex { x = 128.0; y = 0.0; }\n",
+        "linedef { v1 = 0; v2 = 1; sidefront = 0; blocking = true; }\n",
+        "linedef { v1 = 1; v2 = 2; sidefront = 1; blocking = true; }\n",
+        "linedef { v1 = 2; v2 = 3; sidefront = 2; blocking = true; special = 80; arg0 = 1; }\n",
+        "linedef { v1 = 3; v2 = 0; sidefront = 3; blocking = true; }\n",
+        "sidedef { sector = 0; texturemiddle = \"STARTAN3\"; }\n",
+        "sidedef { sector = 0; texturemiddle = \"STARTAN3\"; }\n",
+        "sidedef { sector = 0; texturemiddle = \"SW1START\"; }\n",
+        "sidedef { sector = 0; texturemiddle = \"STARTAN3\"; offsetx = 64; }\n",
+        "sector { texturefloor = \"FLOOR4_8\"; textureceiling = \"CEIL3_5\"; heightceiling = 128; }\n",
+    );
+    vec![
+        ("MAP01", RawData::new()),
+        ("TEXTMAP", text.as_bytes().to_vec()),
+        ("ZNODES", xnod_nodes()),
+        ("ENDMAP", RawData::new()),
+    ]
+}
+
+/// Swap the data of one of the lumps
+pub fn with_lump(mut lumps: TestLumps, lump_name: &str, data: RawData) -> TestLumps {
+    for (name, lump_data) in lumps.iter_mut() {
+        if *name == lump_name {
+            *lump_data = data.clone();
+        }
+    }
+    lumps
+}
+
+/// A PWAD holding the lumps in order, with the directory after the lump data
+pub fn wad_data(lumps: &[(&str, RawData)]) -> RawData {
+    let data_size: usize = lumps.iter().map(|(_, data)| data.len()).sum();
+    let mut wad = b"PWAD".to_vec();
+    wad.extend((lumps.len() as i32).to_le_bytes());
+    wad.extend((12 + data_size as i32).to_le_bytes());
+    let mut directory = RawData::new();
+    for (name, data) in lumps {
+        directory.extend((wad.len() as i32).to_le_bytes());
+        directory.extend((data.len() as i32).to_le_bytes());
+        directory.extend(self::name(name));
+        wad.extend(data);
+    }
+    wad.extend(directory);
+    wad
+}
+
+pub fn wad(lumps: &[(&str, RawData)]) -> Wad {
+    let raw_data = wad_data(lumps);
+    Wad { path: PathBuf::new(), meta: WadMeta::new(&raw_data).unwrap(), raw_data }
+}
+
+pub fn map(lumps: &[(&str, RawData)]) -> Result<Map, Error> {
+    let wad = wad(lumps);
+    Map::new(&wad.meta.lumps, &wad.raw_data, 0)
+}
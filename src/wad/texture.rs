@@ -0,0 +1,241 @@
+use super::*;
+use super::lumps::name_from_bytes;
+
+/// The PNAMES lump, the patch names TEXTUREx definitions refer to by index
+#[derive(Debug, BinRead, PartialEq, Eq, Clone)]
+#[br(little)]
+pub struct PNames {
+    pub count: i32,
+    #[br(count = count, try_map = |names: Vec<[u8; 8]>| names.into_iter().map(|x| name_from_bytes(x.to_vec())).collect())]
+    pub names: Vec<String>,
+}
+
+impl PNames {
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        Self::read(&mut Cursor::new(data))
+            .map_err(|err| Error::Decoding("PNAMES".to_string(), err.root_cause().to_string()))
+    }
+}
+
+/// A patch placed within a texture, `patch_id` indexes into PNAMES
+#[derive(Debug, BinRead, PartialEq, Eq, Copy, Clone)]
+#[br(little)]
+pub struct TexturePatch {
+    pub origin_x: i16,
+    pub origin_y: i16,
+    pub patch_id: i16,
+    pub step_dir: i16,
+    pub colour_map: i16,
+}
+
+#[derive(Debug, BinRead, PartialEq, Eq, Clone)]
+#[br(little)]
+pub struct TextureDefinition {
+    #[br(count = 8, try_map = |x: Vec<u8>| name_from_bytes(x))]
+    pub name: String,
+    pub masked: u32,
+    pub width: i16,
+    pub height: i16,
+    pub column_directory: i32, // unused by the engine
+    pub patch_count: i16,
+    #[br(count = patch_count.max(0))]
+    pub patches: Vec<TexturePatch>,
+}
+
+/// A wall texture built from its patches, patches that couldn't be found or decoded are
+/// listed in `missing_patches` and left transparent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompositeTexture {
+    pub name: String,
+    pub picture: Picture,
+    pub missing_patches: Vec<String>,
+}
+
+/// Every texture definition from TEXTURE1 and TEXTURE2 along with the PNAMES they refer to
+#[derive(Debug, Clone, Default)]
+pub struct Textures {
+    pub patch_names: Vec<String>,
+    pub definitions: Vec<TextureDefinition>,
+    by_name: HashMap<String, usize>,
+}
+
+impl Textures {
+    pub fn new(pnames: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            patch_names: PNames::new(pnames)?.names,
+            ..Self::default()
+        })
+    }
+
+    /// Add the definitions from a TEXTUREx lump, like vanilla the first definition of a name wins
+    pub fn add_texture_lump(&mut self, lump_name: &str, data: &[u8]) -> Result<(), Error> {
+        let error = |reason: String| Error::Decoding(lump_name.to_string(), reason);
+        let read_i32 = |at: usize| data.get(at..at + 4)
+            .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| error(format!("{} bytes is too short to hold a value at {at}", data.len())));
+
+        let count = read_i32(0)?;
+        for i in 0..count.max(0) as usize {
+            let offset = read_i32(4 + i * 4)?;
+            let definition = data.get(offset.max(0) as usize..)
+                .filter(|_| offset >= 0)
+                .ok_or_else(|| error(format!("texture {i} has an offset of {offset} outside of the lump")))
+                .and_then(|definition| TextureDefinition::read(&mut Cursor::new(definition))
+                    .map_err(|err| error(format!("texture {i}: {}", err.root_cause()))))?;
+
+            let name = definition.name.to_ascii_uppercase();
+            if !self.by_name.contains_key(&name) {
+                self.by_name.insert(name, self.definitions.len());
+                self.definitions.push(definition);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn definition(&self, name: &str) -> Option<&TextureDefinition> {
+        let name = name.trim_end_matches('\0').to_ascii_uppercase();
+        self.by_name.get(&name).map(|i| &self.definitions[*i])
+    }
+
+    /// Build a texture from its definition, `patch` looks up and decodes a patch by name
+    pub fn compose<F>(&self, definition: &TextureDefinition, patch: F) -> CompositeTexture
+    where
+        F: Fn(&str) -> Result<Picture, Error>,
+    {
+        let (width, height) = (definition.width.max(0) as usize, definition.height.max(0) as usize);
+        let mut pixels = vec![None; width * height];
+        let mut missing_patches = Vec::new();
+
+        for texture_patch in definition.patches.iter() {
+            let Some(patch_name) = self.patch_names.get(texture_patch.patch_id as usize) else {
+                missing_patches.push(format!("PNAMES #{}", texture_patch.patch_id));
+                continue;
+            };
+            let picture = match patch(patch_name) {
+                Ok(picture) => picture,
+                Err(err) => {
+                    missing_patches.push(format!("{patch_name} ({err})"));
+                    continue;
+                },
+            };
+
+            for y in 0..picture.height as usize {
+                let texture_y = y as isize + texture_patch.origin_y as isize;
+                if texture_y < 0 || texture_y >= height as isize { continue }
+                for x in 0..picture.width as usize {
+                    let texture_x = x as isize + texture_patch.origin_x as isize;
+                    if texture_x < 0 || texture_x >= width as isize { continue }
+                    if let Some(index) = picture.pixel(x, y) {
+                        pixels[texture_y as usize * width + texture_x as usize] = Some(index);
+                    }
+                }
+            }
+        }
+
+        CompositeTexture {
+            name: definition.name.clone(),
+            picture: Picture {
+                width: width as u16,
+                height: height as u16,
+                left_offset: 0,
+                top_offset: 0,
+                pixels,
+            },
+            missing_patches,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_maps::*;
+
+    fn pnames(names: &[&str]) -> RawData {
+        [(names.len() as i32).to_le_bytes().to_vec(), names.iter().flat_map(|patch_name| name(patch_name)).collect()].concat()
+    }
+
+    /// A texture's name, width, height and patches of (x, y, patch id)
+    type TextureSpec<'t> = (&'t str, i16, i16, &'t [(i16, i16, i16)]);
+
+    fn texture_lump(textures: &[TextureSpec]) -> RawData {
+        let mut offsets = (textures.len() as i32).to_le_bytes().to_vec();
+        let mut definitions = Vec::new();
+        for (texture_name, width, height, patches) in textures {
+            offsets.extend((4 + 4 * textures.len() as i32 + definitions.len() as i32).to_le_bytes());
+            definitions.extend(name(texture_name));
+            definitions.extend(0u32.to_le_bytes());
+            definitions.extend(words(&[*width as i32, *height as i32, 0, 0, patches.len() as i32]));
+            for (x, y, patch_id) in patches.iter() {
+                definitions.extend(words(&[*x as i32, *y as i32, *patch_id as i32, 1, 0]));
+            }
+        }
+        [offsets, definitions].concat()
+    }
+
+    /// A solid `width` x `height` picture of a single palette index
+    fn solid(width: u16, height: u16, index: u8) -> Picture {
+        Picture { width, height, left_offset: 0, top_offset: 0, pixels: vec![Some(index); width as usize * height as usize] }
+    }
+
+    fn textures() -> Textures {
+        let mut textures = Textures::new(&pnames(&["WALL00_1", "WALL00_2", "GONE"])).unwrap();
+        textures.add_texture_lump("TEXTURE1", &texture_lump(&[
+            ("STARTAN3", 4, 2, &[(0, 0, 0), (2, -1, 1)]),
+            ("BROKEN", 2, 2, &[(0, 0, 2), (0, 0, 9), (1, 1, 0)]),
+        ])).unwrap();
+        textures.add_texture_lump("TEXTURE2", &texture_lump(&[("startan3", 8, 8, &[])])).unwrap();
+        textures
+    }
+
+    fn patch(patch_name: &str) -> Result<Picture, Error> {
+        match patch_name {
+            "WALL00_1" => Ok(solid(3, 2, 1)),
+            "WALL00_2" => Ok(solid(4, 2, 2)),
+            _ => Err(Error::MissingLump("P_START".to_string(), patch_name.to_string())),
+        }
+    }
+
+    #[test]
+    fn the_first_definition_of_a_name_wins() {
+        let textures = textures();
+        assert_eq!(textures.patch_names, ["WALL00_1", "WALL00_2", "GONE"]);
+        assert_eq!(textures.definitions.len(), 2);
+        let definition = textures.definition("startan3\0").unwrap();
+        assert_eq!((definition.width, definition.height, definition.patches.len()), (4, 2, 2));
+        assert_eq!(textures.definition("BROKEN").unwrap().patches[1].patch_id, 9);
+    }
+
+    #[test]
+    fn later_patches_are_drawn_over_earlier_ones_and_clipped() {
+        let textures = textures();
+        let texture = textures.compose(textures.definition("STARTAN3").unwrap(), patch);
+        assert_eq!(texture.name, "STARTAN3");
+        assert_eq!(texture.missing_patches, Vec::<String>::new());
+        assert_eq!((texture.picture.width, texture.picture.height), (4, 2));
+        // The second patch starts a row above the texture so only its bottom row is drawn
+        assert_eq!(texture.picture.pixels, [
+            Some(1), Some(1), Some(2), Some(2),
+            Some(1), Some(1), Some(1), None,
+        ]);
+    }
+
+    #[test]
+    fn missing_patches_are_listed_and_left_transparent() {
+        let textures = textures();
+        let texture = textures.compose(textures.definition("BROKEN").unwrap(), patch);
+        assert_eq!(texture.missing_patches.len(), 2);
+        assert!(texture.missing_patches[0].starts_with("GONE ("));
+        assert_eq!(texture.missing_patches[1], "PNAMES #9");
+        assert_eq!(texture.picture.pixels, [None, None, None, Some(1)]);
+    }
+
+    #[test]
+    fn definitions_outside_of_the_lump_are_errors() {
+        let mut textures = Textures::default();
+        let mut lump = texture_lump(&[("STARTAN3", 4, 2, &[])]);
+        lump[4..8].copy_from_slice(&1000i32.to_le_bytes());
+        assert!(matches!(textures.add_texture_lump("TEXTURE1", &lump), Err(Error::Decoding(..))));
+        assert!(textures.add_texture_lump("TEXTURE1", &[1, 0]).is_err());
+    }
+}
@@ -0,0 +1,685 @@
+use super::*;
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write};
+
+/// The UDMF namespaces that can be loaded, they decide which properties are standard and
+/// whether the map uses Doom or Hexen style specials
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UdmfNamespace {
+    Doom,
+    Heretic,
+    Hexen,
+    ZDoom,
+}
+
+impl UdmfNamespace {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "doom" => Some(Self::Doom),
+            "heretic" => Some(Self::Heretic),
+            "hexen" => Some(Self::Hexen),
+            "zdoom" => Some(Self::ZDoom),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Doom => "doom",
+            Self::Heretic => "heretic",
+            Self::Hexen => "hexen",
+            Self::ZDoom => "zdoom",
+        }
+    }
+
+    pub fn map_format(&self) -> MapFormat {
+        match self {
+            Self::Doom | Self::Heretic => MapFormat::Doom,
+            Self::Hexen | Self::ZDoom => MapFormat::Hexen,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UdmfValue {
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    /// Any other bare word
+    Keyword(String),
+}
+
+impl UdmfValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Integer(value) => Some(*value as f64),
+            Self::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Integer(value) => Some(*value),
+            Self::Float(value) => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) | Self::Keyword(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Display for UdmfValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(value) => write!(f, "{value}"),
+            // Floats must have a decimal point to be read back as floats
+            Self::Float(value) if value.fract() == 0.0 => write!(f, "{value}.0"),
+            Self::Float(value) => write!(f, "{value}"),
+            Self::Boolean(value) => write!(f, "{value}"),
+            Self::String(value) => write!(f, "\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+            Self::Keyword(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+/// Keys are case insensitive so they're stored lower cased
+pub type UdmfProperties = BTreeMap<String, UdmfValue>;
+
+/// A `thing`, `vertex`, `linedef` etc. block, every property is kept whether or not it's
+/// one the namespace defines
+#[derive(Debug, PartialEq, Clone)]
+pub struct UdmfBlock {
+    pub kind: String,
+    pub properties: UdmfProperties,
+    /// Where the block started in the TEXTMAP
+    pub line: usize,
+    pub column: usize,
+}
+
+impl UdmfBlock {
+    pub fn get(&self, key: &str) -> Option<&UdmfValue> {
+        self.properties.get(key)
+    }
+
+    pub fn float(&self, key: &str, default: f64) -> f64 {
+        self.get(key).and_then(UdmfValue::as_f64).unwrap_or(default)
+    }
+
+    pub fn int(&self, key: &str, default: i64) -> i64 {
+        self.get(key).and_then(UdmfValue::as_i64).unwrap_or(default)
+    }
+
+    pub fn flag(&self, key: &str) -> bool {
+        self.get(key).and_then(UdmfValue::as_bool).unwrap_or(false)
+    }
+
+    pub fn string<'b>(&'b self, key: &str, default: &'b str) -> &'b str {
+        self.get(key).and_then(UdmfValue::as_str).unwrap_or(default)
+    }
+
+    fn required_float(&self, key: &str) -> Result<f64, Error> {
+        self.get(key).and_then(UdmfValue::as_f64).ok_or_else(|| self.missing(key))
+    }
+
+    fn required_int(&self, key: &str) -> Result<i64, Error> {
+        self.get(key).and_then(UdmfValue::as_i64).ok_or_else(|| self.missing(key))
+    }
+
+    fn required_string(&self, key: &str) -> Result<&str, Error> {
+        self.get(key).and_then(UdmfValue::as_str).ok_or_else(|| self.missing(key))
+    }
+
+    fn missing(&self, key: &str) -> Error {
+        Udmf::error(self.line, self.column, format!("{} is missing '{key}'", self.kind))
+    }
+
+    /// An integer property converted to the type its record stores it as
+    fn int_as<T: TryFrom<i64>>(&self, key: &str, value: i64) -> Result<T, Error> {
+        T::try_from(value).map_err(|_| {
+            Udmf::error(self.line, self.column, format!("{} '{key}' of {value} is out of range", self.kind))
+        })
+    }
+
+    /// A coordinate rounded to the nearest map unit
+    fn coordinate(&self, key: &str, value: f64) -> Result<i16, Error> {
+        let rounded = value.round();
+        if !(i16::MIN as f64..=i16::MAX as f64).contains(&rounded) {
+            return Err(Udmf::error(self.line, self.column, format!("{} '{key}' of {value} is out of range", self.kind)));
+        }
+        Ok(rounded as i16)
+    }
+
+    /// The position of the block within its kind, which the record keeps as its id
+    fn id<T: TryFrom<usize>>(&self, id: usize) -> Result<T, Error> {
+        T::try_from(id).map_err(|_| Udmf::error(self.line, self.column, format!("{} {id} is past the last id records can have", self.kind)))
+    }
+
+    /// Hexen style args, `arg0` to `arg4`
+    fn args(&self) -> Result<[u8; 5], Error> {
+        let mut args = [0; 5];
+        for (i, arg) in args.iter_mut().enumerate() {
+            let key = format!("arg{i}");
+            *arg = self.int_as(&key, self.int(&key, 0))?;
+        }
+        Ok(args)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Identifier(String),
+    Value(UdmfValue),
+    Symbol(char),
+}
+
+/// Splits TEXTMAP into tokens, each with the line and column it started at
+struct Tokenizer<'t> {
+    chars: std::iter::Peekable<std::str::Chars<'t>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'t> Tokenizer<'t> {
+    fn new(text: &'t str) -> Self {
+        Self { chars: text.chars().peekable(), line: 1, column: 1 }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while(&mut self, text: &mut String, condition: impl Fn(char) -> bool) {
+        while let Some(c) = self.chars.peek().copied().filter(|c| condition(*c)) {
+            text.push(c);
+            self.bump();
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), Error> {
+        loop {
+            match self.chars.peek() {
+                Some(c) if c.is_whitespace() => { self.bump(); }
+                Some('/') => {
+                    let mut ahead = self.chars.clone();
+                    ahead.next();
+                    match ahead.next() {
+                        Some('/') => while self.bump().is_some_and(|c| c != '\n') {},
+                        Some('*') => {
+                            let (line, column) = (self.line, self.column);
+                            self.bump();
+                            self.bump();
+                            let mut last = ' ';
+                            loop {
+                                match self.bump() {
+                                    Some('/') if last == '*' => break,
+                                    Some(c) => last = c,
+                                    None => return Err(Udmf::error(line, column, "unterminated comment".to_string())),
+                                }
+                            }
+                        }
+                        _ => return Ok(()),
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// The next token and where it started, `None` at the end of the text
+    fn next_token(&mut self) -> Result<Option<(Token, usize, usize)>, Error> {
+        self.skip_whitespace_and_comments()?;
+        let (line, column) = (self.line, self.column);
+        let Some(c) = self.chars.peek().copied() else { return Ok(None) };
+
+        let token = match c {
+            '{' | '}' | '=' | ';' => {
+                self.bump();
+                Token::Symbol(c)
+            }
+            '"' => {
+                self.bump();
+                let mut text = String::new();
+                loop {
+                    match self.bump() {
+                        Some('"') => break,
+                        Some('\\') => text.extend(self.bump()),
+                        Some(c) => text.push(c),
+                        None => return Err(Udmf::error(line, column, "unterminated string".to_string())),
+                    }
+                }
+                Token::Value(UdmfValue::String(text))
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut text = String::new();
+                self.take_while(&mut text, |c| c.is_ascii_alphanumeric() || c == '_');
+                Token::Identifier(text)
+            }
+            c if c.is_ascii_digit() || matches!(c, '+' | '-' | '.') => {
+                let mut text = String::new();
+                self.take_while(&mut text, |c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+                Token::Value(Self::number(&text).ok_or_else(|| Udmf::error(line, column, format!("'{text}' is not a number")))?)
+            }
+            c => return Err(Udmf::error(line, column, format!("unexpected character '{c}'"))),
+        };
+        Ok(Some((token, line, column)))
+    }
+
+    fn number(text: &str) -> Option<UdmfValue> {
+        let (negative, digits) = match text.as_bytes().first()? {
+            b'-' => (true, &text[1..]),
+            b'+' => (false, &text[1..]),
+            _ => (false, text),
+        };
+        let sign = if negative { -1 } else { 1 };
+        if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            i64::from_str_radix(hex, 16).ok().map(|value| UdmfValue::Integer(sign * value))
+        } else if digits.contains(['.', 'e', 'E']) {
+            text.parse().ok().map(UdmfValue::Float)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            i64::from_str_radix(digits, 8).ok().map(|value| UdmfValue::Integer(sign * value))
+        } else {
+            digits.parse::<i64>().ok().map(|value| UdmfValue::Integer(sign * value))
+        }
+    }
+}
+
+/// A parsed UDMF TEXTMAP, blocks are grouped by kind in the order they appeared
+#[derive(Debug, PartialEq, Clone)]
+pub struct Udmf {
+    pub namespace: UdmfNamespace,
+    /// Top level assignments other than the namespace
+    pub globals: UdmfProperties,
+    pub things: Vec<UdmfBlock>,
+    pub vertexes: Vec<UdmfBlock>,
+    pub line_defs: Vec<UdmfBlock>,
+    pub side_defs: Vec<UdmfBlock>,
+    pub sectors: Vec<UdmfBlock>,
+    /// Blocks of any other kind
+    pub other_blocks: Vec<UdmfBlock>,
+}
+
+/// A UDMF map's records converted to their binary equivalents, coordinates are rounded
+pub type UdmfRecords = (Vec<Thing>, Vec<LineDef>, Vec<SideDef>, Vec<Vertex>, Vec<Sector>);
+
+impl Udmf {
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let text = std::str::from_utf8(data)
+            .map_err(|err| Self::error(1, 1, format!("not valid UTF-8: {err}")))?;
+        let mut tokens = Tokenizer::new(text);
+
+        let mut namespace = None;
+        let mut globals = UdmfProperties::new();
+        let mut blocks = Vec::new();
+        while let Some((token, line, column)) = tokens.next_token()? {
+            let Token::Identifier(name) = token else {
+                return Err(Self::error(line, column, "expected a property or block name".to_string()));
+            };
+            let name = name.to_ascii_lowercase();
+            match Self::expect_next(&mut tokens, line, column)? {
+                (Token::Symbol('='), ..) => {
+                    let value = Self::value(&mut tokens, line, column)?;
+                    if name == "namespace" {
+                        let namespace_name = value.as_str().unwrap_or_default();
+                        namespace = Some(UdmfNamespace::from_name(namespace_name)
+                            .ok_or_else(|| Self::error(line, column, format!("namespace '{namespace_name}' is not supported")))?);
+                    } else {
+                        globals.insert(name, value);
+                    }
+                }
+                (Token::Symbol('{'), ..) => blocks.push(Self::block(&mut tokens, name, line, column)?),
+                (_, line, column) => return Err(Self::error(line, column, format!("expected '=' or '{{' after '{name}'"))),
+            }
+        }
+
+        let mut udmf = Self {
+            namespace: namespace.ok_or_else(|| Self::error(1, 1, "no namespace".to_string()))?,
+            globals,
+            things: Vec::new(),
+            vertexes: Vec::new(),
+            line_defs: Vec::new(),
+            side_defs: Vec::new(),
+            sectors: Vec::new(),
+            other_blocks: Vec::new(),
+        };
+        for block in blocks {
+            match block.kind.as_str() {
+                "thing" => udmf.things.push(block),
+                "vertex" => udmf.vertexes.push(block),
+                "linedef" => udmf.line_defs.push(block),
+                "sidedef" => udmf.side_defs.push(block),
+                "sector" => udmf.sectors.push(block),
+                _ => udmf.other_blocks.push(block),
+            }
+        }
+        Ok(udmf)
+    }
+
+    fn expect_next(tokens: &mut Tokenizer, line: usize, column: usize) -> Result<(Token, usize, usize), Error> {
+        tokens.next_token()?.ok_or_else(|| Self::error(line, column, "unexpected end of TEXTMAP".to_string()))
+    }
+
+    /// The value of an assignment along with its terminating `;`
+    fn value(tokens: &mut Tokenizer, line: usize, column: usize) -> Result<UdmfValue, Error> {
+        let value = match Self::expect_next(tokens, line, column)? {
+            (Token::Value(value), ..) => value,
+            (Token::Identifier(word), ..) => match word.to_ascii_lowercase().as_str() {
+                "true" => UdmfValue::Boolean(true),
+                "false" => UdmfValue::Boolean(false),
+                _ => UdmfValue::Keyword(word),
+            },
+            (_, line, column) => return Err(Self::error(line, column, "expected a value".to_string())),
+        };
+        match Self::expect_next(tokens, line, column)? {
+            (Token::Symbol(';'), ..) => Ok(value),
+            (_, line, column) => Err(Self::error(line, column, "expected ';'".to_string())),
+        }
+    }
+
+    fn block(tokens: &mut Tokenizer, kind: String, line: usize, column: usize) -> Result<UdmfBlock, Error> {
+        let mut properties = UdmfProperties::new();
+        loop {
+            match Self::expect_next(tokens, line, column)? {
+                (Token::Symbol('}'), ..) => break,
+                (Token::Identifier(key), key_line, key_column) => match Self::expect_next(tokens, key_line, key_column)? {
+                    (Token::Symbol('='), ..) => {
+                        properties.insert(key.to_ascii_lowercase(), Self::value(tokens, key_line, key_column)?);
+                    }
+                    (_, line, column) => return Err(Self::error(line, column, format!("expected '=' after '{key}'"))),
+                },
+                (_, line, column) => return Err(Self::error(line, column, format!("expected a property or '}}' in {kind}"))),
+            }
+        }
+        Ok(UdmfBlock { kind, properties, line, column })
+    }
+
+    fn error(line: usize, column: usize, reason: String) -> Error {
+        Error::Decoding("TEXTMAP".to_string(), format!("line {line}, column {column}: {reason}"))
+    }
+
+    /// Convert the standard properties of each block into the records binary maps use
+    pub fn to_records(&self) -> Result<UdmfRecords, Error> {
+        let things = self.things.iter().enumerate()
+            .map(|(id, block)| self.thing(id, block))
+            .collect::<Result<_, _>>()?;
+        let line_defs = self.line_defs.iter().enumerate()
+            .map(|(id, block)| self.line_def(id, block))
+            .collect::<Result<_, _>>()?;
+        let side_defs = self.side_defs.iter().enumerate()
+            .map(|(id, block)| Ok(SideDef {
+                x_offset: block.int_as("offsetx", block.int("offsetx", 0))?,
+                y_offset: block.int_as("offsety", block.int("offsety", 0))?,
+                name_of_upper: block.string("texturetop", "-").to_string(),
+                name_of_lower: block.string("texturebottom", "-").to_string(),
+                name_of_middle: block.string("texturemiddle", "-").to_string(),
+                sector_this_sidedef_faces: block.int_as("sector", block.required_int("sector")?)?,
+                id: block.id(id)?,
+            }))
+            .collect::<Result<_, Error>>()?;
+        let vertexes = self.vertexes.iter().enumerate()
+            .map(|(id, block)| Ok(Vertex {
+                x: block.coordinate("x", block.required_float("x")?)?,
+                y: block.coordinate("y", block.required_float("y")?)?,
+                id: block.id(id)?,
+            }))
+            .collect::<Result<_, Error>>()?;
+        let sectors = self.sectors.iter().enumerate()
+            .map(|(id, block)| Ok(Sector {
+                floor_height: block.int_as("heightfloor", block.int("heightfloor", 0))?,
+                ceiling_height: block.int_as("heightceiling", block.int("heightceiling", 0))?,
+                name_of_floor_texture: block.required_string("texturefloor")?.to_string(),
+                name_of_ceiling_texture: block.required_string("textureceiling")?.to_string(),
+                light_level: block.int_as("lightlevel", block.int("lightlevel", 160))?,
+                special_type: block.int_as("special", block.int("special", 0))?,
+                tag: block.int_as("id", block.int("id", 0).max(0))?,
+                id: block.id(id)?,
+            }))
+            .collect::<Result<_, Error>>()?;
+        Ok((things, line_defs, side_defs, vertexes, sectors))
+    }
+
+    fn thing(&self, id: usize, block: &UdmfBlock) -> Result<Thing, Error> {
+        let bit = |key: &str, bit: u16| if block.flag(key) { 1u16 << bit } else { 0 };
+        let mut flags = bit("skill1", 0) | bit("skill2", 0) | bit("skill3", 1)
+            | bit("skill4", 2) | bit("skill5", 2) | bit("ambush", 3);
+        if self.namespace.map_format() == MapFormat::Hexen {
+            flags |= bit("dormant", 4) | bit("class1", 5) | bit("class2", 6) | bit("class3", 7)
+                | bit("single", 8) | bit("coop", 9) | bit("dm", 10);
+        } else if !block.flag("single") && (block.flag("coop") || block.flag("dm")) {
+            flags |= 1 << 4;
+        }
+
+        Ok(Thing {
+            tid: block.int_as("id", block.int("id", 0))?,
+            x: block.coordinate("x", block.required_float("x")?)?,
+            y: block.coordinate("y", block.required_float("y")?)?,
+            z: block.coordinate("height", block.float("height", 0.0))?,
+            angle_facing: block.int_as("angle", block.int("angle", 0))?,
+            doomed_thing_type: block.int_as("type", block.required_int("type")?)?,
+            flags: ThingFlags::from_bytes(flags.to_le_bytes()),
+            special: block.int_as("special", block.int("special", 0))?,
+            args: block.args()?,
+            id: block.id(id)?,
+        })
+    }
+
+    fn line_def(&self, id: usize, block: &UdmfBlock) -> Result<LineDef, Error> {
+        let bit = |key: &str, bit: u16| if block.flag(key) { 1u16 << bit } else { 0 };
+        let mut flags = bit("blocking", 0) | bit("blockmonsters", 1) | bit("twosided", 2)
+            | bit("dontpegtop", 3) | bit("dontpegbottom", 4) | bit("secret", 5)
+            | bit("blocksound", 6) | bit("dontdraw", 7) | bit("mapped", 8);
+        if self.namespace.map_format() == MapFormat::Hexen {
+            let activation = ["playercross", "playeruse", "monstercross", "impact", "playerpush", "missilecross"]
+                .iter().position(|key| block.flag(key)).unwrap_or(0) as u16;
+            flags |= bit("repeatspecial", 9) | activation << 10;
+        }
+
+        Ok(LineDef {
+            start_vertex_id: block.int_as("v1", block.required_int("v1")?)?,
+            end_vertex_id: block.int_as("v2", block.required_int("v2")?)?,
+            flags: LineDefFlags::from_bytes(flags.to_le_bytes()),
+            special_type: block.int_as("special", block.int("special", 0))?,
+            tag: block.int_as("id", block.int("id", 0).max(0))?,
+            args: block.args()?,
+            front: block.int_as("sidefront", block.required_int("sidefront")?)?,
+            back: block.int_as("sideback", block.int("sideback", -1))?,
+            id: block.id(id)?,
+        })
+    }
+
+    /// Write the map back out as TEXTMAP, properties are written sorted by key
+    pub fn to_text(&self) -> String {
+        let mut text = format!("namespace = \"{}\";\n", self.namespace.name());
+        for (key, value) in self.globals.iter() {
+            let _ = writeln!(text, "{key} = {value};");
+        }
+        let blocks = self.things.iter()
+            .chain(self.vertexes.iter())
+            .chain(self.line_defs.iter())
+            .chain(self.side_defs.iter())
+            .chain(self.sectors.iter())
+            .chain(self.other_blocks.iter());
+        for block in blocks {
+            let _ = writeln!(text, "\n{}\n{{", block.kind);
+            for (key, value) in block.properties.iter() {
+                let _ = writeln!(text, "{key} = {value};");
+            }
+            text.push_str("}\n");
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_maps::*;
+
+    fn tokens(text: &str) -> Vec<(Token, usize, usize)> {
+        let mut tokenizer = Tokenizer::new(text);
+        std::iter::from_fn(|| tokenizer.next_token().unwrap()).collect()
+    }
+
+    fn error(text: &str) -> String {
+        match Udmf::new(text.as_bytes()) {
+            Err(Error::Decoding(_, reason)) => reason,
+            result => panic!("expected an error, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn tokens_know_where_they_started() {
+        let text = "thing // a comment\n{\n  x = -1.5e1; /* a\n comment */ type = 0x10;\n  s = \"a \\\"b\\\"\";\n}";
+        assert_eq!(tokens(text), vec![
+            (Token::Identifier("thing".to_string()), 1, 1),
+            (Token::Symbol('{'), 2, 1),
+            (Token::Identifier("x".to_string()), 3, 3),
+            (Token::Symbol('='), 3, 5),
+            (Token::Value(UdmfValue::Float(-15.0)), 3, 7),
+            (Token::Symbol(';'), 3, 13),
+            (Token::Identifier("type".to_string()), 4, 13),
+            (Token::Symbol('='), 4, 18),
+            (Token::Value(UdmfValue::Integer(16)), 4, 20),
+            (Token::Symbol(';'), 4, 24),
+            (Token::Identifier("s".to_string()), 5, 3),
+            (Token::Symbol('='), 5, 5),
+            (Token::Value(UdmfValue::String("a \"b\"".to_string())), 5, 7),
+            (Token::Symbol(';'), 5, 16),
+            (Token::Symbol('}'), 6, 1),
+        ]);
+    }
+
+    #[test]
+    fn numbers_can_be_decimal_octal_hex_or_float() {
+        assert_eq!(Tokenizer::number("42"), Some(UdmfValue::Integer(42)));
+        assert_eq!(Tokenizer::number("-0x1F"), Some(UdmfValue::Integer(-31)));
+        assert_eq!(Tokenizer::number("017"), Some(UdmfValue::Integer(15)));
+        assert_eq!(Tokenizer::number("+.5"), Some(UdmfValue::Float(0.5)));
+        assert_eq!(Tokenizer::number("1-2"), None);
+    }
+
+    #[test]
+    fn blocks_are_grouped_by_kind() {
+        let udmf = Udmf::new(&udmf_room()[1].1).unwrap();
+        assert_eq!(udmf.namespace, UdmfNamespace::ZDoom);
+        assert_eq!(udmf.things.len(), 1);
+        assert_eq!(udmf.vertexes.len(), 4);
+        assert_eq!(udmf.line_defs.len(), 4);
+        assert_eq!(udmf.side_defs.len(), 4);
+        assert_eq!(udmf.sectors.len(), 1);
+        assert_eq!((udmf.things[0].line, udmf.things[0].column), (3, 1));
+        assert_eq!(udmf.line_defs[2].int("special", 0), 80);
+        assert_eq!(udmf.side_defs[2].string("texturemiddle", "-"), "SW1START");
+
+        // Keys are case insensitive and unknown blocks and globals are kept
+        let udmf = Udmf::new(b"Namespace = \"Doom\"; version = 2; Thing { X = 1; Y = 2; Type = 3; } mystery { a = b; }").unwrap();
+        assert_eq!(udmf.namespace, UdmfNamespace::Doom);
+        assert_eq!(udmf.globals.get("version"), Some(&UdmfValue::Integer(2)));
+        assert_eq!(udmf.things[0].int("type", 0), 3);
+        assert_eq!(udmf.other_blocks[0].get("a"), Some(&UdmfValue::Keyword("b".to_string())));
+    }
+
+    #[test]
+    fn namespaces_decide_the_map_format() {
+        for (name, namespace, format) in [
+            ("doom", UdmfNamespace::Doom, MapFormat::Doom),
+            ("heretic", UdmfNamespace::Heretic, MapFormat::Doom),
+            ("hexen", UdmfNamespace::Hexen, MapFormat::Hexen),
+            ("ZDoom", UdmfNamespace::ZDoom, MapFormat::Hexen),
+        ] {
+            let udmf = Udmf::new(format!("namespace = \"{name}\";").as_bytes()).unwrap();
+            assert_eq!(udmf.namespace, namespace);
+            assert_eq!(namespace.map_format(), format);
+        }
+        assert_eq!(error("namespace = \"strife\";"), "line 1, column 1: namespace 'strife' is not supported");
+        assert_eq!(error("thing { x = 1; }"), "line 1, column 1: no namespace");
+    }
+
+    #[test]
+    fn errors_say_where_they_are() {
+        assert_eq!(error("namespace = \"doom\";\nthing\n{\n  x = 1\n}"), "line 5, column 1: expected ';'");
+        assert_eq!(error("namespace = \"doom\";\nthing { x 1; }"), "line 2, column 11: expected '=' after 'x'");
+        assert_eq!(error("namespace = \"doom\";\n  thing { x = 1;"), "line 2, column 3: unexpected end of TEXTMAP");
+        assert_eq!(error("namespace = \"doom\";\nthing { x = 1.2.3; }"), "line 2, column 13: '1.2.3' is not a number");
+        assert_eq!(error("namespace = \"doom\";\nthing { x = \"1; }"), "line 2, column 13: unterminated string");
+        assert_eq!(error("namespace = \"doom\";\n/* thing"), "line 2, column 1: unterminated comment");
+        assert_eq!(error("namespace = \"doom\";\nthing { x = #; }"), "line 2, column 13: unexpected character '#'");
+    }
+
+    #[test]
+    fn blocks_become_records() {
+        let udmf = Udmf::new(&udmf_room()[1].1).unwrap();
+        let (things, line_defs, side_defs, vertexes, sectors) = udmf.to_records().unwrap();
+        assert_eq!((things[0].x, things[0].y, things[0].angle_facing, things[0].doomed_thing_type), (32, 32, 90, 1));
+        assert_eq!(vertexes.iter().map(|vertex| (vertex.x, vertex.y)).collect::<Vec<_>>(), vec![(0, 0), (0, 128), (128, 128), (128, 0)]);
+        assert_eq!((line_defs[2].start_vertex_id, line_defs[2].end_vertex_id), (2, 3));
+        assert_eq!((line_defs[2].special_type, line_defs[2].args), (80, [1, 0, 0, 0, 0]));
+        assert_eq!((line_defs[0].front, line_defs[0].back), (0, -1));
+        assert_eq!((side_defs[3].x_offset, side_defs[3].name_of_middle.as_str(), side_defs[3].name_of_upper.as_str()), (64, "STARTAN3", "-"));
+        assert_eq!((sectors[0].floor_height, sectors[0].ceiling_height, sectors[0].light_level), (0, 128, 160));
+        assert_eq!(sectors[0].name_of_floor_texture, "FLOOR4_8");
+    }
+
+    #[test]
+    fn values_records_cant_hold_are_errors() {
+        let records = |block: &str| Udmf::new(format!("namespace = \"zdoom\";\n{block}").as_bytes()).unwrap().to_records();
+        let reason = |block: &str| match records(block) {
+            Err(Error::Decoding(_, reason)) => reason,
+            result => panic!("expected an error, got {result:?}"),
+        };
+        assert_eq!(reason("thing { x = 0; y = 0; type = 1; arg0 = 256; }"), "line 2, column 1: thing 'arg0' of 256 is out of range");
+        assert_eq!(reason("thing { x = 40000.0; y = 0; type = 1; }"), "line 2, column 1: thing 'x' of 40000 is out of range");
+        assert_eq!(reason("vertex { x = 0; y = -32768.6; }"), "line 2, column 1: vertex 'y' of -32768.6 is out of range");
+        assert_eq!(reason("sidedef { sector = 70000; }"), "line 2, column 1: sidedef 'sector' of 70000 is out of range");
+        assert_eq!(reason("sidedef { sector = 0; offsetx = -40000; }"), "line 2, column 1: sidedef 'offsetx' of -40000 is out of range");
+        assert_eq!(reason("sector { texturefloor = \"A\"; textureceiling = \"B\"; heightfloor = 32768; }"), "line 2, column 1: sector 'heightfloor' of 32768 is out of range");
+        assert_eq!(reason("linedef { v1 = -1; v2 = 1; sidefront = 0; }"), "line 2, column 1: linedef 'v1' of -1 is out of range");
+        assert_eq!(reason("linedef { v1 = 0; v2 = 1; }"), "line 2, column 1: linedef is missing 'sidefront'");
+        assert!(records("vertex { x = 0; y = -32768.4; }").is_ok());
+    }
+
+    #[test]
+    fn written_text_reads_back_the_same() {
+        let udmf = Udmf::new(&udmf_room()[1].1).unwrap();
+        let text = udmf.to_text();
+        assert!(text.starts_with("namespace = \"zdoom\";\n\nthing\n{\nangle = 90;\nskill1 = true;\ntype = 1;\nx = 32.0;\ny = 32.0;\n}\n"));
+        let read_back = Udmf::new(text.as_bytes()).unwrap();
+        let positions = |udmf: &Udmf| udmf.things.iter().chain(udmf.side_defs.iter()).map(|block| block.properties.clone()).collect::<Vec<_>>();
+        assert_eq!(positions(&read_back), positions(&udmf));
+    }
+
+    #[test]
+    fn maps_without_an_endmap_stop_at_the_next_map() {
+        let mut lumps = udmf_room();
+        lumps.pop();
+        lumps.extend(square_room());
+        let wad = wad(&lumps);
+        assert_eq!(Map::map_end(&wad.meta.lumps, 0), 3);
+        assert_eq!(Map::map_end(&wad.meta.lumps, 3), lumps.len());
+        assert_eq!(Map::new(&wad.meta.lumps, &wad.raw_data, 3).unwrap().line_defs.len(), 4);
+
+        let wad = self::wad(&udmf_room());
+        assert_eq!(Map::map_end(&wad.meta.lumps, 0), 4);
+    }
+}
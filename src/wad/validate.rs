@@ -0,0 +1,507 @@
+use super::*;
+
+use std::ops::Range;
+use std::sync::OnceLock;
+use std::fmt::{self, Display};
+
+/// How bad a problem found by the validator is, errors will stop a wad from loading or
+/// crash the engine whereas warnings are tolerated (as vanilla does) but likely mistakes
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found in a wad, `location` is the lump or map record at fault
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub location: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn error(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { severity: Severity::Error, location: location.into(), message: message.into() }
+    }
+
+    pub fn warning(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { severity: Severity::Warning, location: location.into(), message: message.into() }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.location, self.message)
+    }
+}
+
+pub type Problems = Vec<Problem>;
+
+impl Wad {
+    /// Check the directory and every map in the wad, reporting every problem found rather
+    /// than stopping at the first
+    pub fn validate(&self) -> Problems {
+        let mut problems = self.validate_directory();
+        for lump in self.meta.lumps.iter().filter(|lump| lump.kind == LumpKind::MapMarker) {
+            problems.extend(self.validate_map(lump));
+        }
+        problems
+    }
+
+    /// Decode a map's lumps one by one as they're stored, rather than through `Map::new`
+    /// which stops at the first bad lump and builds any missing nodes, then check the
+    /// references between whatever records could be read
+    fn validate_map(&self, marker: &Lump) -> Problems {
+        let mut problems = Problems::new();
+        let map_name = marker.name.as_str();
+        let map_lumps = Map::map_lumps(&self.meta.lumps, marker.index);
+        // Lumps outside of the file are reported with the directory, here they're just missing
+        let data = |lump_name: &str| map_lumps.get(lump_name).and_then(|lump| lump.data(&self.raw_data).ok());
+        let at = |lump_name: &str| format!("{map_name} {lump_name}");
+
+        let behavior = data("BEHAVIOR");
+        let format = if behavior.is_some() { MapFormat::Hexen } else { MapFormat::Doom };
+        // Records that can't be read are kept as None so the rest keep their ids
+        let (things, line_defs, side_defs, vertexes, sectors) = if let Some(text) = data("TEXTMAP") {
+            match Udmf::new(text).and_then(|udmf| udmf.to_records()) {
+                Ok((things, line_defs, side_defs, vertexes, sectors)) =>
+                    (all(things), all(line_defs), all(side_defs), all(vertexes), all(sectors)),
+                Err(err) => {
+                    problems.push(Problem::error(at("TEXTMAP"), err.to_string()));
+                    return problems;
+                }
+            }
+        } else {
+            for lump_name in ["THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SECTORS"] {
+                if !map_lumps.contains_key(lump_name) {
+                    problems.push(Problem::error(at(lump_name), "is missing"));
+                }
+            }
+            (
+                self.map_records(&map_lumps, map_name, "THINGS", format.thing_size(), |id| (id, format), &mut problems),
+                self.map_records(&map_lumps, map_name, "LINEDEFS", format.line_def_size(), |id| (id, format), &mut problems),
+                self.map_records(&map_lumps, map_name, "SIDEDEFS", 30, |id| (id,), &mut problems),
+                self.map_records(&map_lumps, map_name, "VERTEXES", 4, |id| (id,), &mut problems),
+                self.map_records(&map_lumps, map_name, "SECTORS", 26, |id| (id,), &mut problems),
+            )
+        };
+
+        let extended = ["NODES", "SSECTORS", "ZNODES"].into_iter()
+            .find_map(|lump_name| data(lump_name)
+                .filter(|data| ExtendedFormat::from_nodes(data).or_else(|| ExtendedFormat::from_sub_sectors(data)).is_some())
+                .map(|data| (lump_name, data)));
+        let (vertexes, segments, sub_sectors, nodes) = if let Some((lump_name, extended)) = extended {
+            let map_vertexes: Vec<Vertex> = vertexes.iter().flatten().copied().collect();
+            let map_line_defs: Vec<LineDef> = line_defs.iter().flatten().cloned().collect();
+            match ExtendedNodes::new(extended, &map_vertexes, &map_line_defs) {
+                Ok(extended) => (all(extended.map_vertexes()), all(extended.segments), all(extended.sub_sectors), all(extended.nodes)),
+                Err(err) => {
+                    problems.push(Problem::error(at(lump_name), err.to_string()));
+                    (vertexes, Vec::new(), Vec::new(), Vec::new())
+                }
+            }
+        } else if data("SEGS").unwrap_or_default().is_empty() || data("SSECTORS").unwrap_or_default().is_empty() {
+            problems.push(Problem::warning(map_name, "has no nodes, they'll be built when the map is loaded"));
+            (vertexes, Vec::new(), Vec::new(), Vec::new())
+        } else {
+            (
+                vertexes,
+                self.map_records(&map_lumps, map_name, "SEGS", 12, |id| (id,), &mut problems),
+                self.map_records(&map_lumps, map_name, "SSECTORS", 4, |id| (id,), &mut problems),
+                self.map_records(&map_lumps, map_name, "NODES", 28, |id| (id,), &mut problems),
+            )
+        };
+        if nodes.is_empty() && sub_sectors.len() > 1 {
+            problems.push(Problem::warning(at("NODES"), "is empty, nodes will be built when the map is loaded"));
+        }
+
+        let block_map = match data("BLOCKMAP").filter(|data| !data.is_empty()) {
+            None => {
+                problems.push(Problem::warning(at("BLOCKMAP"), "is missing or empty"));
+                None
+            }
+            Some(data) => BlockMap::new(data)
+                .map_err(|err| problems.push(Problem::error(at("BLOCKMAP"), err.to_string())))
+                .ok(),
+        };
+        if let Err(err) = Map::decode_gl_nodes(&map_lumps, &self.raw_data) {
+            problems.push(Problem::warning(format!("{map_name} GL nodes"), err.to_string()));
+        }
+
+        let counts = RecordCounts {
+            vertexes: vertexes.len(),
+            line_defs: line_defs.len(),
+            side_defs: side_defs.len(),
+            sectors: sectors.len(),
+            segments: segments.len(),
+            sub_sectors: sub_sectors.len(),
+            nodes: nodes.len(),
+        };
+        let map = Map {
+            name: map_name.to_string(),
+            format,
+            reject: RejectTable::new(data("REJECT").unwrap_or_default(), sectors.len()),
+            things: things.into_iter().flatten().collect(),
+            line_defs: line_defs.into_iter().flatten().collect(),
+            side_defs: side_defs.into_iter().flatten().collect(),
+            vertexes: vertexes.into_iter().flatten().collect(),
+            segments: segments.into_iter().flatten().collect(),
+            sub_sectors: sub_sectors.into_iter().flatten().collect(),
+            nodes: nodes.into_iter().flatten().collect(),
+            sectors: sectors.into_iter().flatten().collect(),
+            block_map,
+            gl_nodes: None,
+            behavior: None,
+            udmf: None,
+            node_source: NodeSource::Lumps,
+            lumps: Vec::new(),
+            map_points: OnceLock::new(),
+            map_bounds: OnceLock::new(),
+            line_defs_to_vertexes: OnceLock::new(),
+        };
+        problems.extend(map.validate_references(&counts));
+        problems.extend(map.validate_reject());
+        problems.extend(map.validate_block_map(counts.line_defs));
+        problems
+    }
+
+    /// Read every whole record in a map lump, reporting those that can't be read and any
+    /// bytes left over rather than giving up on the lump. Records are kept in place so
+    /// references to those after a bad one still find the right record
+    fn map_records<T, A>(
+        &self,
+        map_lumps: &HashMap<&str, &Lump>,
+        map_name: &str,
+        lump_name: &str,
+        record_size: usize,
+        args: impl Fn(u16) -> A,
+        problems: &mut Problems,
+    ) -> Vec<Option<T>>
+    where
+        T: for<'a> BinRead<Args<'a> = A>,
+    {
+        let location = format!("{map_name} {lump_name}");
+        let Some(data) = map_lumps.get(lump_name).and_then(|lump| lump.data(&self.raw_data).ok()) else {
+            return Vec::new();
+        };
+        if data.len() % record_size != 0 {
+            problems.push(Problem::warning(&location, format!(
+                "is {} bytes, {} more than a whole number of {record_size} byte records", data.len(), data.len() % record_size
+            )));
+        }
+
+        // Records keep their id in 16 bits, any past that are left unread
+        let count = data.len() / record_size;
+        if count > u16::MAX as usize + 1 {
+            problems.push(Problem::error(&location, format!("has {count} records, more than the {} records can be numbered", u16::MAX as usize + 1)));
+        }
+        let mut cursor = Cursor::new(data);
+        (0..count).map(|id| {
+            let id_16 = u16::try_from(id).ok()?;
+            cursor.set_position((id * record_size) as u64);
+            T::read_le_args(&mut cursor, args(id_16))
+                .map_err(|err| problems.push(Problem::error(format!("{location} record {id}"), err.root_cause().to_string())))
+                .ok()
+        }).collect()
+    }
+
+    /// Lumps must lie within the file after the header and not overlap the directory or
+    /// each other, and their names must be up to 8 printable characters padded with NULs
+    fn validate_directory(&self) -> Problems {
+        let mut problems = Problems::new();
+        let file_size = self.raw_data.len() as i64;
+        let dir_start = self.meta.dir_offset as i64;
+        let directory = dir_start..dir_start + self.meta.lumps.len() as i64 * 16;
+        let overlaps = |a: &Range<i64>, b: &Range<i64>| a.start < b.end && b.start < a.end;
+
+        let mut in_bounds: Vec<(Range<i64>, &Lump)> = Vec::new();
+        for lump in self.meta.lumps.iter() {
+            let location = format!("lump {} '{}'", lump.index, lump.name);
+            self.validate_lump_name(lump, &location, &mut problems);
+
+            let range = lump.offset as i64..lump.offset as i64 + lump.size as i64;
+            if lump.offset < 0 || lump.size < 0 {
+                problems.push(Problem::error(location, format!("negative offset {} or size {}", lump.offset, lump.size)));
+            } else if range.end > file_size {
+                problems.push(Problem::error(location, format!(
+                    "{} bytes at offset {} run past the end of the {file_size} byte file", lump.size, lump.offset
+                )));
+            } else if lump.size > 0 && range.start < 12 {
+                problems.push(Problem::error(location, "overlaps the wad header"));
+            } else if lump.size > 0 && overlaps(&range, &directory) {
+                problems.push(Problem::error(location, "overlaps the directory"));
+            } else if lump.size > 0 {
+                in_bounds.push((range, lump));
+            }
+        }
+
+        // Identical ranges are lumps deliberately sharing data, only partial overlaps are suspect
+        in_bounds.sort_by_key(|(range, _)| (range.start, range.end));
+        for (i, (range, lump)) in in_bounds.iter().enumerate() {
+            for (other_range, other) in in_bounds[i + 1..].iter().take_while(|(other, _)| other.start < range.end) {
+                if other_range != range {
+                    problems.push(Problem::warning(
+                        format!("lump {} '{}'", lump.index, lump.name),
+                        format!("overlaps lump {} '{}'", other.index, other.name),
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    fn validate_lump_name(&self, lump: &Lump, location: &str, problems: &mut Problems) {
+        let entry = self.meta.dir_offset as usize + lump.index * 16 + 8;
+        let Some(raw_name) = self.raw_data.get(entry..entry + 8) else { return };
+        let length = raw_name.iter().position(|b| *b == 0).unwrap_or(8);
+
+        if length == 0 {
+            problems.push(Problem::error(location, "has an empty name"));
+        } else if !raw_name[..length].iter().all(|b| b.is_ascii_graphic()) {
+            problems.push(Problem::error(location, "name has characters that aren't printable ASCII"));
+        } else if raw_name[..length].iter().any(|b| b.is_ascii_lowercase()) {
+            problems.push(Problem::warning(location, "name has lower case letters"));
+        }
+        if raw_name[length..].iter().any(|b| *b != 0) {
+            problems.push(Problem::warning(location, "name has junk after its terminating NUL"));
+        }
+    }
+}
+
+fn all<T>(records: Vec<T>) -> Vec<Option<T>> {
+    records.into_iter().map(Some).collect()
+}
+
+/// How many records there are to refer to in each lump, when validating a wad this
+/// includes any records that couldn't be read
+#[derive(Debug, Clone, Copy)]
+struct RecordCounts {
+    vertexes: usize,
+    line_defs: usize,
+    side_defs: usize,
+    sectors: usize,
+    segments: usize,
+    sub_sectors: usize,
+    nodes: usize,
+}
+
+impl Map {
+    /// Check the loaded map, its references, REJECT and BLOCKMAP
+    pub fn validate(&self) -> Problems {
+        let mut problems = self.validate_references(&RecordCounts {
+            vertexes: self.vertexes.len(),
+            line_defs: self.line_defs.len(),
+            side_defs: self.side_defs.len(),
+            sectors: self.sectors.len(),
+            segments: self.segments.len(),
+            sub_sectors: self.sub_sectors.len(),
+            nodes: self.nodes.len(),
+        });
+        problems.extend(self.validate_reject());
+        problems.extend(self.validate_block_map(self.line_defs.len()));
+        problems
+    }
+
+    /// Check that every reference between the map's records points at something that exists
+    fn validate_references(&self, counts: &RecordCounts) -> Problems {
+        let mut problems = Problems::new();
+        let location = |what: &str, id: u32| format!("{} {what} {id}", self.name);
+        let vertex_count = counts.vertexes;
+        let side_def_count = counts.side_defs;
+
+        for line_def in self.line_defs.iter() {
+            let at = location("linedef", line_def.id as u32);
+            for vertex_id in [line_def.start_vertex_id, line_def.end_vertex_id] {
+                if vertex_id as usize >= vertex_count {
+                    problems.push(Problem::error(&at, format!("vertex {vertex_id} doesn't exist, there are {vertex_count}")));
+                }
+            }
+            if line_def.front < 0 || line_def.front as usize >= side_def_count {
+                problems.push(Problem::error(&at, format!("front sidedef {} doesn't exist, there are {side_def_count}", line_def.front)));
+            }
+            if line_def.back >= 0 && line_def.back as usize >= side_def_count {
+                problems.push(Problem::error(&at, format!("back sidedef {} doesn't exist, there are {side_def_count}", line_def.back)));
+            }
+            if line_def.flags.two_sided() && line_def.back < 0 {
+                problems.push(Problem::warning(&at, "is flagged two sided but has no back sidedef"));
+            }
+        }
+
+        for side_def in self.side_defs.iter() {
+            let sector = side_def.sector_this_sidedef_faces;
+            if sector < 0 || sector as usize >= counts.sectors {
+                problems.push(Problem::error(
+                    location("sidedef", side_def.id as u32),
+                    format!("sector {sector} doesn't exist, there are {}", counts.sectors),
+                ));
+            }
+        }
+
+        for segment in self.segments.iter() {
+            let at = location("seg", segment.id);
+            for vertex_id in [segment.start_vertext_id, segment.end_verext_id] {
+                if vertex_id as usize >= vertex_count {
+                    problems.push(Problem::error(&at, format!("vertex {vertex_id} doesn't exist, there are {vertex_count}")));
+                }
+            }
+            if let Some(line_def_id) = segment.line_def_id.filter(|id| *id as usize >= counts.line_defs) {
+                problems.push(Problem::error(&at, format!(
+                    "linedef {line_def_id} doesn't exist, there are {}", counts.line_defs
+                )));
+            }
+        }
+
+        for sub_sector in self.sub_sectors.iter() {
+            let at = location("subsector", sub_sector.id);
+            let end = sub_sector.first_segments_id as usize + sub_sector.segments_count as usize;
+            if sub_sector.segments_count == 0 {
+                problems.push(Problem::error(&at, "has no segs"));
+            } else if end > counts.segments {
+                problems.push(Problem::error(&at, format!(
+                    "segs {}..{end} run past the {} segs", sub_sector.first_segments_id, counts.segments
+                )));
+            }
+        }
+
+        for node in self.nodes.iter() {
+            let at = location("node", node.id);
+            for child_id in [node.front_child_id, node.back_child_id] {
+                let (kind, id, count) = if child_id >= SubSector::SUB_SECTOR_IDENTIFIER {
+                    ("subsector", child_id & SubSector::IDENTIFIER_BITMASK, counts.sub_sectors)
+                } else {
+                    ("node", child_id, counts.nodes)
+                };
+                if id as usize >= count {
+                    problems.push(Problem::error(&at, format!("child {kind} {id} doesn't exist, there are {count}")));
+                }
+            }
+        }
+        problems
+    }
+
+    fn validate_reject(&self) -> Problems {
+        let mut problems = Problems::new();
+        if !self.reject.is_expected_size() {
+            problems.push(Problem::warning(format!("{} REJECT", self.name), format!(
+                "is {} bytes but {} sectors need {}",
+                self.reject.data.len(), self.reject.sector_count, RejectTable::table_size(self.reject.sector_count)
+            )));
+        }
+        problems
+    }
+
+    /// A missing BLOCKMAP is reported by whoever knows why it's missing
+    fn validate_block_map(&self, line_def_count: usize) -> Problems {
+        let mut problems = Problems::new();
+        match &self.block_map {
+            None => {}
+            Some(block_map) => {
+                let listed = block_map.block_lists.iter().flatten().filter(|id| **id as usize >= line_def_count);
+                if let Some(line_def_id) = listed.max() {
+                    problems.push(Problem::error(format!("{} BLOCKMAP", self.name), format!(
+                        "lists linedef {line_def_id} but there are {}", line_def_count
+                    )));
+                }
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_maps::*;
+
+    fn locations(problems: &Problems, severity: Severity) -> Vec<&str> {
+        problems.iter().filter(|problem| problem.severity == severity).map(|problem| problem.location.as_str()).collect()
+    }
+
+    #[test]
+    fn a_clean_map_has_no_problems() {
+        assert_eq!(wad(&square_room()).validate(), Problems::new());
+    }
+
+    #[test]
+    fn every_problem_in_a_broken_map_is_reported() {
+        let lumps = with_lump(square_room(), "LINEDEFS", words(&[
+            0, 9, 1, 0, 0, 0, -1,
+            1, 2, 1, 0, 0, 1, -1,
+            2, 3, 1, 11, 0, 2, -1,
+            3, 0, 1, 0, 0, 3, -1,
+        ]));
+        let mut side_defs = lumps.iter().find(|(name, _)| *name == "SIDEDEFS").unwrap().1.clone();
+        side_defs.truncate(side_defs.len() - 10);
+        let lumps = with_lump(lumps, "SIDEDEFS", side_defs);
+        let lumps = with_lump(lumps, "BLOCKMAP", words(&[-8, -8, 1, 1, 40, 0, -1]));
+        let mut lumps = with_lump(lumps, "REJECT", vec![0, 0, 0]);
+        lumps.extend([
+            ("GL_VERT", b"gNd3".to_vec()),
+            ("GL_SEGS", RawData::new()),
+            ("GL_SSECT", RawData::new()),
+            ("GL_NODES", RawData::new()),
+        ]);
+
+        let problems = wad(&lumps).validate();
+        assert_eq!(locations(&problems, Severity::Error), [
+            "E1M1 BLOCKMAP",
+            "E1M1 linedef 0",
+            "E1M1 linedef 3",
+        ]);
+        assert_eq!(locations(&problems, Severity::Warning), [
+            "E1M1 SIDEDEFS",
+            "E1M1 GL nodes",
+            "E1M1 REJECT",
+        ]);
+    }
+
+    #[test]
+    fn maps_without_nodes_are_not_built_before_validating() {
+        let lumps = with_lump(square_room(), "SEGS", RawData::new());
+        let lumps = with_lump(lumps, "NODES", RawData::new());
+        let problems = wad(&lumps).validate();
+        assert_eq!(locations(&problems, Severity::Error), Vec::<&str>::new());
+        assert_eq!(locations(&problems, Severity::Warning), ["E1M1"]);
+    }
+
+    #[test]
+    fn minisegs_have_no_linedef_to_check() {
+        let lumps = with_lump(square_room(), "SEGS", words(&[
+            0, 1, 16384, 0, 0, 0,
+            3, 0, -32768, -1, 0, 0,
+            1, 2, 0, 1, 0, 0,
+            2, 3, -16384, 2, 0, 0,
+        ]));
+        assert_eq!(map(&lumps).unwrap().segments[1].line_def_id, None);
+        assert_eq!(wad(&lumps).validate(), Problems::new());
+    }
+
+    #[test]
+    fn maps_with_an_empty_nodes_lump_are_not_built_before_validating() {
+        let problems = wad(&with_lump(square_room(), "NODES", RawData::new())).validate();
+        assert_eq!(locations(&problems, Severity::Error), Vec::<&str>::new());
+        assert_eq!(locations(&problems, Severity::Warning), ["E1M1 NODES"]);
+    }
+
+    #[test]
+    fn bad_records_are_skipped_and_the_rest_checked() {
+        let lumps = with_lump(square_room(), "SEGS", words(&[
+            0, 1, 16384, 0, 0, 0,
+            3, 0, -32768, 3, 2, 64,
+            1, 2, 0, 9, 0, 0,
+            2, 3, -16384, 2, 0, 0,
+        ]));
+        let problems = wad(&lumps).validate();
+        assert_eq!(locations(&problems, Severity::Error), ["E1M1 SEGS record 1", "E1M1 seg 2"]);
+    }
+}
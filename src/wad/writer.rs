@@ -0,0 +1,261 @@
+use super::*;
+
+/// Builds a WAD file from maps and raw lumps, lumps are written in the order they're added
+#[derive(Debug)]
+pub struct WadWriter {
+    pub identification: Identification,
+    pub lumps: Vec<(String, RawData)>,
+}
+
+impl Default for WadWriter {
+    fn default() -> Self {
+        Self::new(Identification::PWAD)
+    }
+}
+
+impl WadWriter {
+    pub fn new(identification: Identification) -> Self {
+        Self {
+            identification,
+            lumps: Vec::new(),
+        }
+    }
+
+    /// Add a lump as is
+    pub fn add_lump(&mut self, name: &str, data: RawData) -> Result<&mut Self, Error> {
+        if name.is_empty() || name.len() > 8 || !name.is_ascii() {
+            return Err(Error::Writing(format!("'{name}' is not a valid lump name, names must be 1 to 8 ascii characters")));
+        }
+        self.lumps.push((name.to_string(), data));
+        Ok(self)
+    }
+
+    /// Add a lump made up of serialized records
+    pub fn add_records<T>(&mut self, name: &str, records: &[T]) -> Result<&mut Self, Error>
+    where
+        T: for<'a> BinWrite<Args<'a> = ()>,
+    {
+        self.add_records_with(name, records, ())
+    }
+
+    /// Add a lump made up of records serialized with the given binrw args
+    pub fn add_records_with<T, A>(&mut self, name: &str, records: &[T], args: A) -> Result<&mut Self, Error>
+    where
+        T: for<'a> BinWrite<Args<'a> = A>,
+        A: Clone,
+    {
+        let mut cursor = Cursor::new(RawData::new());
+        for record in records {
+            record.write_le_args(&mut cursor, args.clone())
+                .map_err(|err| Error::Writing(format!("couldn't serialize '{name}': {}", err.root_cause())))?;
+        }
+        self.add_lump(name, cursor.into_inner())
+    }
+
+    /// Add a map marker followed by the map's lumps. Maps that were read from a wad keep
+    /// their lumps in the order they were read, with those the map's records came from
+    /// serialized again and the rest (GL nodes, extended nodes, SCRIPTS and so on) as
+    /// they were. Maps put together in memory are written in the order the engine expects
+    pub fn add_map(&mut self, map: &Map) -> Result<&mut Self, Error> {
+        self.add_lump(&map.name, RawData::new())?;
+        if !map.lumps.is_empty() {
+            for (name, data) in map.lumps.iter() {
+                self.add_map_lump(map, name, data)?;
+            }
+            return Ok(self);
+        }
+
+        if let Some(udmf) = &map.udmf {
+            self.add_lump("TEXTMAP", udmf.to_text().into_bytes())?;
+            if let Some(behavior) = &map.behavior {
+                self.add_lump("BEHAVIOR", behavior.clone())?;
+            }
+            return self.add_lump("ENDMAP", RawData::new());
+        }
+        self.add_records_with("THINGS", &map.things, (map.format,))?
+            .add_records_with("LINEDEFS", &map.line_defs, (map.format,))?
+            .add_records("SIDEDEFS", &map.side_defs)?
+            .add_records("VERTEXES", &map.vertexes)?
+            .add_records("SEGS", &map.segments)?
+            .add_records("SSECTORS", &map.sub_sectors)?
+            .add_records("NODES", &map.nodes)?
+            .add_records("SECTORS", &map.sectors)?
+            .add_lump("REJECT", map.reject.data.clone())?
+            .add_records("BLOCKMAP", map.block_map.as_slice())?;
+        if let Some(behavior) = &map.behavior {
+            self.add_lump("BEHAVIOR", behavior.clone())?;
+        }
+        Ok(self)
+    }
+
+    /// Write one of a map's lumps from the records read from it, or as it was read
+    fn add_map_lump(&mut self, map: &Map, name: &str, data: &RawData) -> Result<&mut Self, Error> {
+        let from_lumps = map.node_source == NodeSource::Lumps;
+        match name {
+            // UDMF maps' records come from TEXTMAP, which is only written out again when
+            // it's been changed as that sorts the properties
+            "TEXTMAP" => match &map.udmf {
+                Some(udmf) if Udmf::new(data).ok().as_ref() != Some(udmf) => self.add_lump(name, udmf.to_text().into_bytes()),
+                _ => self.add_lump(name, data.clone()),
+            },
+            _ if map.udmf.is_some() => self.add_lump(name, data.clone()),
+            "THINGS" => self.add_records_with(name, &map.things, (map.format,)),
+            "LINEDEFS" => self.add_records_with(name, &map.line_defs, (map.format,)),
+            "SIDEDEFS" => self.add_records(name, &map.side_defs),
+            "SECTORS" => self.add_records(name, &map.sectors),
+            // Extended and built nodes add vertexes of their own after the map's
+            "VERTEXES" if from_lumps => self.add_records(name, &map.vertexes),
+            "VERTEXES" => self.add_records(name, &map.vertexes[..(data.len() / 4).min(map.vertexes.len())]),
+            "SEGS" if from_lumps => self.add_records(name, &map.segments),
+            "SSECTORS" if from_lumps => self.add_records(name, &map.sub_sectors),
+            "NODES" if from_lumps => self.add_records(name, &map.nodes),
+            "REJECT" => self.add_lump(name, map.reject.data.clone()),
+            // A BLOCKMAP that couldn't be read is kept as it was
+            "BLOCKMAP" if map.block_map.is_some() => self.add_records(name, map.block_map.as_slice()),
+            "BEHAVIOR" => self.add_lump(name, map.behavior.clone().unwrap_or_else(|| data.clone())),
+            _ => self.add_lump(name, data.clone()),
+        }
+    }
+
+    /// Serialize the header, lump data and directory
+    pub fn to_bytes(&self) -> Result<RawData, Error> {
+        let data_size: usize = self.lumps.iter().map(|(_, data)| data.len()).sum();
+        let dir_offset = 12 + data_size;
+        if dir_offset + self.lumps.len() * 16 > i32::MAX as usize {
+            return Err(Error::Writing(format!("{dir_offset} bytes of lump data is too large for a wad")));
+        }
+
+        let mut cursor = Cursor::new(RawData::with_capacity(dir_offset + self.lumps.len() * 16));
+        self.identification.write_le(&mut cursor)
+            .map_err(|err| Error::Writing(err.root_cause().to_string()))?;
+        let mut out = cursor.into_inner();
+        out.extend((self.lumps.len() as i32).to_le_bytes());
+        out.extend((dir_offset as i32).to_le_bytes());
+
+        let mut directory = RawData::with_capacity(self.lumps.len() * 16);
+        for (name, data) in self.lumps.iter() {
+            directory.extend((out.len() as i32).to_le_bytes());
+            directory.extend((data.len() as i32).to_le_bytes());
+            directory.extend(lumps::string_to_bytes(name));
+            out.extend(data);
+        }
+        out.extend(directory);
+        Ok(out)
+    }
+
+    /// Write the wad out to a file
+    pub fn write(&self, path: &std::path::Path) -> Result<(), Error> {
+        fs::write(path, self.to_bytes()?).map_err(|err| Error::Writing(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_maps::*;
+
+    /// Read the map from the wad and write it back out on its own
+    fn write_back(original: &RawData) -> (Map, RawData) {
+        let meta = WadMeta::new(original).unwrap();
+        let map = Map::new(&meta.lumps, original, 0).unwrap();
+        let mut writer = WadWriter::default();
+        writer.add_map(&map).unwrap();
+        (map, writer.to_bytes().unwrap())
+    }
+
+    fn assert_lumps_equal(written: &RawData, original: &RawData) {
+        let meta = WadMeta::new(original).unwrap();
+        let written_meta = WadMeta::new(written).unwrap();
+        let names = |meta: &WadMeta| meta.lumps.iter().map(|lump| lump.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&written_meta), names(&meta));
+        for (lump, written_lump) in meta.lumps.iter().zip(written_meta.lumps.iter()) {
+            assert_eq!(written_lump.data(written).unwrap(), lump.data(original).unwrap(), "{} differs", lump.name);
+        }
+        assert_eq!(written, original);
+    }
+
+    #[test]
+    fn unmodified_map_writes_back_byte_identical() {
+        let original = wad_data(&square_room());
+        let (map, written) = write_back(&original);
+        assert!(map.block_map.is_some());
+        assert!(!map.reject.data.is_empty());
+        assert_eq!(map.nodes.len(), 1);
+        assert_lumps_equal(&written, &original);
+    }
+
+    #[test]
+    fn gl_nodes_are_written_back() {
+        let original = wad_data(&gl_room());
+        let (map, written) = write_back(&original);
+        assert!(map.gl_nodes.is_some());
+        assert_lumps_equal(&written, &original);
+    }
+
+    #[test]
+    fn extended_nodes_are_written_back() {
+        let original = wad_data(&extended_room());
+        let (map, written) = write_back(&original);
+        assert_eq!(map.node_source, NodeSource::Extended(ExtendedFormat::Normal));
+        assert_lumps_equal(&written, &original);
+    }
+
+    #[test]
+    fn udmf_maps_are_written_back() {
+        let original = wad_data(&udmf_room());
+        let (map, written) = write_back(&original);
+        assert_eq!(map.node_source, NodeSource::Extended(ExtendedFormat::Normal));
+        assert_eq!(map.line_defs.len(), 4);
+        assert_lumps_equal(&written, &original);
+    }
+
+    #[test]
+    fn hexen_maps_are_written_back() {
+        let original = wad_data(&hexen_room());
+        let (map, written) = write_back(&original);
+        assert_eq!(map.format, MapFormat::Hexen);
+        assert_eq!(map.things[1].tid, 5);
+        assert_eq!(map.line_defs[2].special_type, 80);
+        assert_lumps_equal(&written, &original);
+    }
+
+    #[test]
+    fn changed_records_are_written() {
+        let original = wad_data(&extended_room());
+        let meta = WadMeta::new(&original).unwrap();
+        let mut map = Map::new(&meta.lumps, &original, 0).unwrap();
+        map.things[0].angle_facing = 270;
+        let mut writer = WadWriter::default();
+        writer.add_map(&map).unwrap();
+        let written = writer.to_bytes().unwrap();
+
+        let written_map = Map::new(&WadMeta::new(&written).unwrap().lumps, &written, 0).unwrap();
+        assert_eq!(written_map.things[0].angle_facing, 270);
+        assert_eq!(written_map.vertexes.len(), 4);
+        assert_eq!(written_map.nodes, map.nodes);
+    }
+
+    #[test]
+    fn changed_udmf_maps_are_written_from_their_textmap() {
+        let original = wad_data(&udmf_room());
+        let meta = WadMeta::new(&original).unwrap();
+        let mut map = Map::new(&meta.lumps, &original, 0).unwrap();
+        let udmf = map.udmf.as_mut().unwrap();
+        udmf.things[0].properties.insert("angle".to_string(), UdmfValue::Integer(270));
+        let mut writer = WadWriter::default();
+        writer.add_map(&map).unwrap();
+        let written = writer.to_bytes().unwrap();
+
+        let written_map = Map::new(&WadMeta::new(&written).unwrap().lumps, &written, 0).unwrap();
+        assert_eq!(written_map.things[0].angle_facing, 270);
+        assert_eq!(written_map.nodes, map.nodes);
+    }
+
+    #[test]
+    fn lump_names_must_fit_the_directory() {
+        let mut writer = WadWriter::default();
+        assert!(writer.add_lump("", RawData::new()).is_err());
+        assert!(writer.add_lump("TOOLONGNAME", RawData::new()).is_err());
+        assert!(writer.add_lump("PLAYPAL", RawData::new()).is_ok());
+    }
+}